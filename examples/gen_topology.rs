@@ -1,12 +1,78 @@
+// Generate clock tree topology of a placed design, without liberty or CTS config
+//
+// cargo run --example gen_topology -- design.yml plugin.yml blif_clk_net [max_branch]
 use std::env::*;
-use sym_cts_rs::ClockTree;
+use std::error::Error;
+use std::process;
+use sym_cts_rs::error::CtsError;
+use sym_cts_rs::{load_design, ClockTree, DesignPlugin, GenTopologyCfg, Sink};
+
 fn main() {
-    let mut cns = ClockTree::new("blif_clk_net");
     let cli_arg: Vec<String> = args().collect();
-    println!("read in verilog file {:?}", cli_arg[1]);
-    println!("read in def file {:?}", cli_arg[2]);
+    if cli_arg.len() < 4 {
+        eprintln!(
+            "usage: {} <design cfg> <plugin cfg> <clock net> [max_branch]",
+            cli_arg[0]
+        );
+        process::exit(64);
+    }
+    let max_branch = match cli_arg.get(4).map(|v| v.parse()) {
+        Some(Ok(b)) => b,
+        Some(Err(e)) => {
+            eprintln!("bad max_branch: {}", e);
+            process::exit(64);
+        }
+        None => 4,
+    };
+    let cfg = GenTopologyCfg {
+        max_branch,
+        min_branch: None,
+        grouping: None,
+        balance: None,
+        merging: None,
+    };
+    let result = build_tree(&cli_arg[1], &cli_arg[2], &cli_arg[3]).and_then(|mut tree| {
+        tree.gen_topology(&cfg)?;
+        Ok(tree)
+    });
+    let mut tree = result.unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    });
+    let mut fanout: Vec<(usize, u32)> = tree.fanout_map.iter().map(|(l, b)| (*l, *b)).collect();
+    fanout.sort_unstable();
+    println!(
+        "sinks:{}, fanout of each level:{:?}",
+        tree.sinks.len(),
+        fanout
+    );
+    if let Some(wirelength) = tree.routing() {
+        println!("wirelength:{}", wirelength.values().sum::<u32>());
+    }
+}
 
-    let _ = cns
-        .run(&cli_arg[1], &cli_arg[2], &cli_arg[3], &cli_arg[4])
-        .unwrap();
+// clock tree of the sinks on the clock net, driven by its top level pin
+fn build_tree(design_path: &str, plugin_path: &str, clock: &str) -> Result<ClockTree, CtsError> {
+    let design: Box<dyn DesignPlugin> = load_design(plugin_path, design_path)?;
+    let plugin_error = |e: Box<dyn Error>| CtsError::DesignImport {
+        path: design_path.to_string(),
+        reason: e.to_string(),
+    };
+    let sinks = design
+        .get_clock_sinks(clock)
+        .map_err(plugin_error)?
+        .into_iter()
+        .map(|(name, location)| Sink {
+            name,
+            location,
+            ..Default::default()
+        });
+    let mut builder = ClockTree::builder(clock).sinks(sinks);
+    if let Some((lower_left, upper_right)) = design.get_die_area().map_err(plugin_error)? {
+        builder = builder.die_area(lower_left, upper_right);
+    }
+    if let Some(source) = design.get_pin_location(clock).map_err(plugin_error)? {
+        builder = builder.source(source);
+    }
+    builder.build()
 }
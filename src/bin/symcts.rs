@@ -0,0 +1,168 @@
+// command line entry of SymCTS
+//
+// exit code is 0 on success, 64 on bad command line, otherwise CtsError::exit_code
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
+use std::process;
+use sym_cts_rs::{run_symcts_with, set_verbosity, validate_config, RunMode, RunOptions};
+
+const USAGE_EXIT_CODE: i32 = 64;
+
+const EXIT_CODES: &str = "EXIT CODES:
+    0     success
+    1     failure without specific cause
+    2     file access error
+    3     config parse error
+    4-6   plugin load, symbol or login error
+    7     design import error
+    8     no clock sink
+    9     bad topology
+    10-11 liberty error or missing buffer model
+    12    buffer insertion error
+    13    export error
+    14    timing analysis error
+    64    bad command line";
+
+fn cfg_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("design")
+            .short("d")
+            .long("design")
+            .value_name("FILE")
+            .help("design config with verilog and DEF paths")
+            .required(true),
+        Arg::with_name("plugin")
+            .short("p")
+            .long("plugin")
+            .value_name("FILE")
+            .help("plugin config with plugin library and login")
+            .required(true),
+        Arg::with_name("cts")
+            .short("c")
+            .long("cts")
+            .value_name("FILE")
+            .help("CTS config of clocks and export")
+            .required(true),
+    ]
+}
+
+fn seed_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("seed")
+        .short("s")
+        .long("seed")
+        .value_name("N")
+        .help("seed of random decisions, overrides the seed in CTS config")
+        .validator(|v| {
+            v.parse::<u64>()
+                .map(|_| ())
+                .map_err(|e| format!("bad seed {}: {}", v, e))
+        })
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("symcts")
+        .about("Symmetric clock tree synthesis")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .after_help(EXIT_CODES)
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .global(true)
+                .help("print details of each stage"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .global(true)
+                .conflicts_with("verbose")
+                .help("print errors only"),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("synthesize clock trees and export DEF and netlist")
+                .args(&cfg_args())
+                .arg(seed_arg())
+                .arg(
+                    Arg::with_name("output-dir")
+                        .short("o")
+                        .long("output-dir")
+                        .value_name("DIR")
+                        .help(
+                            "write DEF and netlist into DIR instead of export paths of CTS config",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("topology-only")
+                .about("generate clock tree topology without buffering, routing or export")
+                .args(&cfg_args())
+                .arg(seed_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("synthesize clock trees and report timing without export")
+                .args(&cfg_args())
+                .arg(seed_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("validate-config")
+                .about("check config files without loading the plugin")
+                .args(&cfg_args()),
+        )
+}
+
+fn main() {
+    let matches = app().get_matches_safe().unwrap_or_else(|e| match e.kind {
+        ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+        _ => {
+            eprintln!("{}", e.message);
+            process::exit(USAGE_EXIT_CODE);
+        }
+    });
+    let (name, sub) = match matches.subcommand() {
+        (name, Some(sub)) => (name, sub),
+        _ => process::exit(USAGE_EXIT_CODE),
+    };
+    // global flags are propagated to subcommand
+    if sub.is_present("quiet") {
+        set_verbosity(0);
+    } else {
+        set_verbosity(1 + sub.occurrences_of("verbose") as u8);
+    }
+
+    let (design, plugin, cts) = cfg_paths(sub);
+    let result = match name {
+        "validate-config" => validate_config(design, plugin, cts).map(|_| {
+            if !sub.is_present("quiet") {
+                println!("config is valid");
+            }
+        }),
+        _ => {
+            let mode = match name {
+                "topology-only" => RunMode::TopologyOnly,
+                "report" => RunMode::Report,
+                _ => RunMode::Full,
+            };
+            let options = RunOptions {
+                mode,
+                output_dir: sub.value_of("output-dir").map(|d| d.to_string()),
+                seed: sub.value_of("seed").and_then(|v| v.parse().ok()),
+            };
+            run_symcts_with(design, plugin, cts, &options)
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn cfg_paths<'a>(sub: &'a ArgMatches) -> (&'a str, &'a str, &'a str) {
+    // required by every subcommand
+    let path = |name: &str| sub.value_of(name).unwrap_or_default();
+    (path("design"), path("plugin"), path("cts"))
+}
@@ -0,0 +1,396 @@
+// build and synthesize clock tree from sinks given by the caller, without design plugin
+use crate::error::{CtsError, CtsStage};
+use crate::liberty::Liberty;
+use crate::model::*;
+use crate::stage::cfg::{ClockCfg, DummyLoadCfg, MergeEmbedding};
+use crate::stage::timing::TimingReport;
+use rand::rngs::StdRng;
+use std::collections::HashSet;
+
+pub struct ClockTreeBuilder {
+    name: String,
+    sinks: Vec<Sink>,
+    source: Option<Location>,
+    die_area: Option<(Location, Location)>,
+    obstacles: Vec<(Location, Location)>,
+}
+
+impl ClockTreeBuilder {
+    pub fn sink(mut self, name: &str, location: Location, cap: f32) -> Self {
+        self.sinks.push(Sink {
+            name: name.to_string(),
+            location,
+            cap,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn sinks<I: IntoIterator<Item = Sink>>(mut self, sinks: I) -> Self {
+        self.sinks.extend(sinks);
+        self
+    }
+
+    // clock source driving the tree, such as the clock port
+    pub fn source(mut self, location: Location) -> Self {
+        self.source = Some(location);
+        self
+    }
+
+    pub fn die_area(mut self, lower_left: Location, upper_right: Location) -> Self {
+        self.die_area = Some((lower_left, upper_right));
+        self
+    }
+
+    // placement and routing blockages and fixed macros, as (lower left, upper right)
+    pub fn obstacles<I: IntoIterator<Item = (Location, Location)>>(mut self, obstacles: I) -> Self {
+        self.obstacles.extend(obstacles);
+        self
+    }
+
+    /// Check the sinks and make an unsynthesized clock tree
+    ///
+    /// Sink names must be unique as they are instance names in export. When die area is
+    /// set, the sinks and the source must be inside it. x_range and y_range are the
+    /// bounding box of the sinks. Sinks may be inside obstacles, such as the clock pin of
+    /// a macro.
+    pub fn build(self) -> Result<ClockTree, CtsError> {
+        if self.sinks.is_empty() {
+            return Err(CtsError::EmptySinks);
+        }
+        let mut names = HashSet::new();
+        if let Some(s) = self.sinks.iter().find(|s| !names.insert(s.name.as_str())) {
+            return Err(CtsError::BadTopology(format!(
+                "sink {} is duplicated",
+                s.name
+            )));
+        }
+        if let Some((lower_left, upper_right)) = self.die_area {
+            if lower_left.0 >= upper_right.0 || lower_left.1 >= upper_right.1 {
+                return Err(CtsError::BadTopology(format!(
+                    "die area {:?} {:?} is empty",
+                    lower_left, upper_right
+                )));
+            }
+            let inside = |d: Location| {
+                (lower_left.0..=upper_right.0).contains(&d.0)
+                    && (lower_left.1..=upper_right.1).contains(&d.1)
+            };
+            if let Some(s) = self.sinks.iter().find(|s| !inside(s.location)) {
+                return Err(CtsError::BadTopology(format!(
+                    "sink {} at {:?} is outside die area",
+                    s.name, s.location
+                )));
+            }
+            if let Some(source) = self.source.filter(|d| !inside(*d)) {
+                return Err(CtsError::BadTopology(format!(
+                    "clock source at {:?} is outside die area",
+                    source
+                )));
+            }
+        }
+        if let Some((lower_left, upper_right)) = self
+            .obstacles
+            .iter()
+            .find(|(ll, ur)| ll.0 >= ur.0 || ll.1 >= ur.1)
+        {
+            return Err(CtsError::BadTopology(format!(
+                "obstacle {:?} {:?} is empty",
+                lower_left, upper_right
+            )));
+        }
+        let x = self.sinks.iter().map(|s| s.location.0);
+        let y = self.sinks.iter().map(|s| s.location.1);
+        Ok(ClockTree {
+            name: self.name,
+            x_range: (x.clone().min().unwrap_or(0), x.max().unwrap_or(0)),
+            y_range: (y.clone().min().unwrap_or(0), y.max().unwrap_or(0)),
+            source: self.source,
+            die_area: self.die_area,
+            obstacles: self.obstacles,
+            sinks: self.sinks,
+            ..Default::default()
+        })
+    }
+}
+
+impl ClockTree {
+    pub fn builder(name: &str) -> ClockTreeBuilder {
+        ClockTreeBuilder {
+            name: name.to_string(),
+            sinks: vec![],
+            source: None,
+            die_area: None,
+            obstacles: vec![],
+        }
+    }
+
+    /// Run the stages on the sinks of the tree
+    ///
+    /// Topology generation, merge embedding, buffering and routing update the tree, then
+    /// timing analysis reports it. Merge units are moved out of obstacles before buffering.
+    /// When sinks request arrival offsets or have insertion delay, wire of each child is
+    /// lengthened for them before routing. Pseudo sinks take the input capacitance of
+    /// `dummy_load` when it's set, from grouping on. The report is None when the tree can't
+    /// be timed with the buffers in `liberty`.
+    pub fn synthesize(
+        &mut self,
+        clock_cfg: &ClockCfg,
+        liberty: &Liberty,
+        wire: &WireModel,
+        dummy_load: Option<&DummyLoadCfg>,
+        rng: &mut StdRng,
+    ) -> Result<Option<TimingReport>, CtsError> {
+        if let Some(dummy_load) = dummy_load {
+            self.dummy_cap = liberty
+                .get_pin_cap(&dummy_load.cell, &dummy_load.input_pin)
+                .ok_or_else(|| {
+                    CtsError::Liberty(format!(
+                        "pin {} of dummy load {} is not found",
+                        dummy_load.input_pin, dummy_load.cell
+                    ))
+                })
+                .map_err(|e| e.in_stage(CtsStage::GenTopology))?;
+        }
+        // groups are balanced with the capacitance of pseudo sinks set above, and so is
+        // Elmore delay, of the tree without buffers
+        self.gen_topology(&clock_cfg.stage1_cfg)
+            .map_err(|e| e.in_stage(CtsStage::GenTopology))?;
+        let embedded = match clock_cfg.stage1_cfg.merging {
+            Some(MergeEmbedding::Dme) => self.embed_dme(None),
+            Some(MergeEmbedding::ElmoreDme) => self.embed_dme(Some(wire)),
+            Some(MergeEmbedding::Midpoint) | None => Ok(()),
+        };
+        embedded.map_err(|e| e.in_stage(CtsStage::GenTopology))?;
+        self.legalize();
+        let buffering_stage = |e: CtsError| e.in_stage(CtsStage::Buffering);
+        let buffer_lib = liberty
+            .get_buffer_lib(&clock_cfg.stage2_cfg.buffer_list)
+            .map_err(buffering_stage)?;
+        self.buffering(&clock_cfg.stage2_cfg, &buffer_lib, wire, rng)
+            .map_err(buffering_stage)?;
+        if self
+            .sinks
+            .iter()
+            .any(|s| !s.dummy && (s.offset != 0.0 || s.latency != 0.0))
+        {
+            self.plan_skew(&buffer_lib, wire, clock_cfg.stage2_cfg.input_slew)
+                .ok_or_else(|| {
+                    CtsError::BadTopology("skew can't be planned".to_string())
+                        .in_stage(CtsStage::Buffering)
+                })?;
+        }
+        if self.routing().is_none() {
+            return Err(
+                CtsError::BadTopology("merge unit can't be routed".to_string())
+                    .in_stage(CtsStage::Routing),
+            );
+        }
+        let wire_cap: f32 = (0..self.merges.len())
+            .map(|m| self.get_merge_unit_wire_cap(m, wire))
+            .sum();
+        detail!("routed wire capacitance:{}", wire_cap);
+        Ok(self.timing_analysis(
+            &buffer_lib,
+            wire,
+            clock_cfg.stage2_cfg.input_slew,
+            clock_cfg.stage2_cfg.max_slew,
+            clock_cfg
+                .stage2_cfg
+                .delay_metric
+                .unwrap_or(DelayMetric::Elmore),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stage::cfg::{BufferingCfg, GenTopologyCfg};
+    use crate::stage::timing::SinkTiming;
+    use crate::test_util::new_wire;
+    use rand::SeedableRng;
+
+    const LIBERTY: &str = r#"
+library (test) {
+  lu_table_template (delay_template) {
+    variable_1 : input_net_transition ;
+    variable_2 : total_output_net_capacitance ;
+    index_1 ("0.01, 0.5");
+    index_2 ("0.001, 0.1");
+  }
+  cell (BUF_X1) {
+    area : 1.0 ;
+    pin (A) { direction : input ; capacitance : 0.002 ; }
+    pin (Z) { direction : output ; max_capacitance : 0.1 ; function : "A" ;
+      timing () { related_pin : "A" ;
+        cell_rise (delay_template) { values ("0.02, 0.10", "0.04, 0.12"); }
+        cell_fall (delay_template) { values ("0.02, 0.10", "0.04, 0.12"); }
+        rise_transition (delay_template) { values ("0.01, 0.20", "0.02, 0.22"); }
+        fall_transition (delay_template) { values ("0.01, 0.20", "0.02, 0.22"); }
+      }
+    }
+  }
+  cell (DFF_X1) {
+    pin (CK) { direction : input ; clock : true ; capacitance : 0.001 ; }
+  }
+}
+"#;
+
+    fn new_cfg() -> ClockCfg {
+        ClockCfg {
+            name: "clk".to_string(),
+            stage1_cfg: GenTopologyCfg {
+                max_branch: 4,
+                min_branch: None,
+                grouping: None,
+                balance: None,
+                merging: None,
+            },
+            stage2_cfg: BufferingCfg {
+                buffer_list: vec!["BUF_X1".to_string()],
+                liberty_paths: vec![],
+                input_slew: 0.05,
+                max_slew: 0.3,
+                rho_matrix_path: String::new(),
+                delay_metric: None,
+                pop_size: 16,
+                elite_size: 2,
+                mutation_rate: 0.1,
+                generations: 10,
+            },
+            latency_offsets: None,
+        }
+    }
+
+    fn grid_builder(num: i32) -> ClockTreeBuilder {
+        (0..num).fold(ClockTree::builder("clk"), |builder, i| {
+            builder.sink(
+                &format!("ff_{}", i),
+                ((i % 5) * 2000 + 1000, (i / 5) * 2000 + 1000),
+                0.001,
+            )
+        })
+    }
+
+    #[test]
+    fn build_checks_sinks() {
+        let tree = grid_builder(12).build().unwrap();
+        assert_eq!(tree.x_range, (1000, 9000));
+        assert_eq!(tree.y_range, (1000, 5000));
+
+        assert!(matches!(
+            ClockTree::builder("clk").build(),
+            Err(CtsError::EmptySinks)
+        ));
+        let duplicated = grid_builder(2).sink("ff_0", (0, 0), 0.001);
+        assert!(duplicated.build().is_err());
+        let die = ((0, 0), (10000, 10000));
+        assert!(grid_builder(12).die_area(die.0, die.1).build().is_ok());
+        assert!(grid_builder(30).die_area(die.0, die.1).build().is_err());
+        let outside = grid_builder(12).die_area(die.0, die.1).source((-10, 0));
+        assert!(outside.build().is_err());
+    }
+
+    #[test]
+    fn synthesize_without_plugin() {
+        let mut liberty = Liberty::default();
+        liberty.load_str(LIBERTY).unwrap();
+        let wire = new_wire();
+        let mut tree = grid_builder(13)
+            .source((0, 0))
+            .die_area((0, 0), (10000, 6000))
+            .build()
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let report = tree
+            .synthesize(&new_cfg(), &liberty, &wire, None, &mut rng)
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.sinks.len(), tree.sinks.len());
+        assert!(report.min_latency > 0.0);
+        assert!(report.skew >= 0.0 && report.skew <= report.max_latency);
+        // pseudo sinks are left out of latency and the per-sink lines
+        let real: Vec<&SinkTiming> = report.sinks.iter().filter(|s| !s.dummy).collect();
+        assert_eq!(real.len(), 13);
+        let min_latency = real.iter().map(|s| s.latency).fold(f32::MAX, f32::min);
+        assert_eq!(report.min_latency, min_latency);
+        let text = report.to_string();
+        assert!(text.contains("sinks:13\n"));
+        assert!(text.contains("\n  ff_12 latency:"));
+        assert_eq!(text.matches("\n  ff_").count(), 13);
+        tree.check_consistency().unwrap();
+    }
+
+    #[test]
+    fn delay_metric_from_cfg() {
+        let mut liberty = Liberty::default();
+        liberty.load_str(LIBERTY).unwrap();
+        let wire = new_wire();
+        let mut latency = vec![];
+        for metric in [None, Some(DelayMetric::D2M)] {
+            let mut cfg = new_cfg();
+            cfg.stage2_cfg.delay_metric = metric;
+            let mut tree = grid_builder(13).source((0, 0)).build().unwrap();
+            let mut rng = StdRng::seed_from_u64(1);
+            let report = tree
+                .synthesize(&cfg, &liberty, &wire, None, &mut rng)
+                .unwrap()
+                .unwrap();
+            latency.push(report.max_latency);
+        }
+        // the same tree is timed, D2M is below the Elmore bound
+        assert!(latency[1] > 0.0 && latency[1] < latency[0]);
+    }
+
+    #[test]
+    fn same_seed_synthesizes_same_tree() {
+        let mut liberty = Liberty::default();
+        liberty.load_str(LIBERTY).unwrap();
+        let wire = new_wire();
+        let run = || {
+            let mut tree = grid_builder(23).source((0, 0)).build().unwrap();
+            let mut rng = StdRng::seed_from_u64(1);
+            let report = tree
+                .synthesize(&new_cfg(), &liberty, &wire, None, &mut rng)
+                .unwrap()
+                .unwrap();
+            let buffers: Vec<(String, Location, usize)> = tree
+                .buffers
+                .iter()
+                .map(|b| (b.model_name.clone(), b.location, b.load))
+                .collect();
+            let mut fanout: Vec<(usize, u32)> =
+                tree.fanout_map.iter().map(|(l, b)| (*l, *b)).collect();
+            fanout.sort_unstable();
+            let latency: Vec<u32> = report.sinks.iter().map(|s| s.latency.to_bits()).collect();
+            (buffers, fanout, latency, report.to_string())
+        };
+        let first = run();
+        assert!(!first.0.is_empty());
+        assert!(first == run());
+    }
+
+    #[test]
+    fn synthesize_with_latency_offsets() {
+        let mut liberty = Liberty::default();
+        liberty.load_str(LIBERTY).unwrap();
+        let wire = new_wire();
+        let mut tree = grid_builder(12).build().unwrap();
+        tree.sinks[0].offset = 0.05;
+        tree.sinks[7].offset = 0.02;
+        let mut rng = StdRng::seed_from_u64(1);
+        let report = tree
+            .synthesize(&new_cfg(), &liberty, &wire, None, &mut rng)
+            .unwrap()
+            .unwrap();
+        let requested: Vec<f32> = report.sinks.iter().map(|s| s.requested).collect();
+        assert_eq!(requested[0], 0.05);
+        assert_eq!(requested[7], 0.02);
+        assert!((report.sinks[0].offset - 0.05).abs() < 0.001);
+        assert!((report.sinks[7].offset - 0.02).abs() < 0.001);
+        assert!(report.max_offset_error < 0.001);
+        assert!(report.to_string().contains(" requested:0.05"));
+        tree.check_consistency().unwrap();
+    }
+}
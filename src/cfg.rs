@@ -1,19 +1,19 @@
-use cts_plugin::CTSPlugin;
+use crate::error::CtsError;
+use crate::plugin::DesignPlugin;
 use libloading::Library;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
 #[derive(Serialize, Deserialize)]
-struct DesignCfg {
-    verilog_path: String,
-    def_path: String,
+pub struct DesignCfg {
+    pub verilog_path: String,
+    pub def_path: String,
 }
 
 impl DesignCfg {
-    pub fn new<P: AsRef<std::path::Path>>(
-        path: P,
-    ) -> std::result::Result<DesignCfg, Box<dyn Error>> {
-        let cfg_str = std::fs::read_to_string(path)?;
-        let design_cfg: DesignCfg = serde_yaml::from_str(&cfg_str)?;
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> std::result::Result<DesignCfg, CtsError> {
+        let path = path.as_ref().display().to_string();
+        let cfg_str = std::fs::read_to_string(&path).map_err(CtsError::io(&path))?;
+        let design_cfg: DesignCfg =
+            serde_yaml::from_str(&cfg_str).map_err(CtsError::config(&path))?;
         Ok(design_cfg)
     }
 }
@@ -25,19 +25,39 @@ struct PluginCfg {
     password: String,
 }
 
+// symbol of the plugin library making a `DesignPlugin`, `new_design_plugin` makes only a
+// `CTSPlugin` and can't be taken as one
+const DESIGN_PLUGIN_SYMBOL: &str = "new_cts_export_plugin";
+
 impl PluginCfg {
-    pub fn new<P: AsRef<std::path::Path>>(
+    pub fn load<P: AsRef<std::path::Path>>(
         path: P,
-    ) -> std::result::Result<Box<dyn CTSPlugin>, Box<dyn Error>> {
-        let cfg_str = std::fs::read_to_string(path)?;
-        let plg_cfg: PluginCfg = serde_yaml::from_str(&cfg_str)?;
-        let cts_lib = Library::new(&plg_cfg.plugin_path)?;
-        let new_design_plugin: libloading::Symbol<fn() -> Box<dyn CTSPlugin>> =
-            unsafe { cts_lib.get(b"new_design_plugin") }?;
+    ) -> std::result::Result<Box<dyn DesignPlugin>, CtsError> {
+        let path = path.as_ref().display().to_string();
+        let cfg_str = std::fs::read_to_string(&path).map_err(CtsError::io(&path))?;
+        let plg_cfg: PluginCfg = serde_yaml::from_str(&cfg_str).map_err(CtsError::config(&path))?;
+        let cts_lib = Library::new(&plg_cfg.plugin_path).map_err(|e| CtsError::PluginLoad {
+            path: plg_cfg.plugin_path.clone(),
+            reason: e.to_string(),
+        })?;
+        let new_design_plugin: libloading::Symbol<fn() -> Box<dyn DesignPlugin>> = unsafe {
+            cts_lib.get(DESIGN_PLUGIN_SYMBOL.as_bytes())
+        }
+        .map_err(|e| CtsError::PluginSymbol {
+            symbol: DESIGN_PLUGIN_SYMBOL.to_string(),
+            reason: e.to_string(),
+        })?;
         // allocate
         let mut design_plugin = new_design_plugin();
-        design_plugin.login(&plg_cfg.username, &plg_cfg.password)?;
-        println!(
+        // the code of the plugin lives in the library, keep it loaded as long as the process
+        std::mem::forget(cts_lib);
+        design_plugin
+            .login(&plg_cfg.username, &plg_cfg.password)
+            .map_err(|e| CtsError::Login {
+                username: plg_cfg.username.clone(),
+                reason: e.to_string(),
+            })?;
+        info!(
             "Successfully login in to Vulcan PDK database with {}",
             &plg_cfg.username
         );
@@ -45,13 +65,30 @@ impl PluginCfg {
     }
 }
 
+// parse plugin config and check the plugin library exists, without loading it
+pub fn check_plugin_cfg<P: AsRef<std::path::Path>>(path: P) -> std::result::Result<(), CtsError> {
+    let path = path.as_ref().display().to_string();
+    let cfg_str = std::fs::read_to_string(&path).map_err(CtsError::io(&path))?;
+    let plg_cfg: PluginCfg = serde_yaml::from_str(&cfg_str).map_err(CtsError::config(&path))?;
+    std::fs::metadata(&plg_cfg.plugin_path).map_err(CtsError::io(&plg_cfg.plugin_path))?;
+    Ok(())
+}
+
 pub fn load_design<P: AsRef<std::path::Path>>(
     p1: P,
     p2: P,
-) -> std::result::Result<Box<dyn CTSPlugin>, Box<dyn Error>> {
-    let mut plg = PluginCfg::new(p1)?;
+) -> std::result::Result<Box<dyn DesignPlugin>, CtsError> {
+    let mut plg = PluginCfg::load(p1)?;
     let design = DesignCfg::new(p2)?;
-    plg.import_verilog(&design.verilog_path)?;
-    plg.import_def(&design.def_path)?;
+    plg.import_verilog(&design.verilog_path)
+        .map_err(|e| CtsError::DesignImport {
+            path: design.verilog_path.clone(),
+            reason: e.to_string(),
+        })?;
+    plg.import_def(&design.def_path)
+        .map_err(|e| CtsError::DesignImport {
+            path: design.def_path.clone(),
+            reason: e.to_string(),
+        })?;
     Ok(plg)
 }
@@ -1 +1,156 @@
-pub enum MyError {}
+use std::error::Error;
+use std::fmt;
+
+// stage of the flow where an error happened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CtsStage {
+    LoadDesign,
+    GenTopology,
+    Buffering,
+    Routing,
+    Timing,
+    Export,
+}
+
+#[derive(Debug)]
+pub enum CtsError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    ConfigParse {
+        path: String,
+        reason: String,
+    },
+    PluginLoad {
+        path: String,
+        reason: String,
+    },
+    PluginSymbol {
+        symbol: String,
+        reason: String,
+    },
+    Login {
+        username: String,
+        reason: String,
+    },
+    DesignImport {
+        path: String,
+        reason: String,
+    },
+    EmptySinks,
+    BadTopology(String),
+    Liberty(String),
+    MissingBufferModel(Vec<String>),
+    Ga(String),
+    Export(String),
+    Timing(String),
+    Stage {
+        stage: CtsStage,
+        source: Box<CtsError>,
+    },
+}
+
+impl CtsError {
+    pub fn in_stage(self, stage: CtsStage) -> CtsError {
+        match self {
+            CtsError::Stage { .. } => self,
+            _ => CtsError::Stage {
+                stage,
+                source: Box::new(self),
+            },
+        }
+    }
+
+    pub fn stage(&self) -> Option<CtsStage> {
+        match self {
+            CtsError::Stage { stage, .. } => Some(*stage),
+            _ => None,
+        }
+    }
+
+    // error without stage context
+    pub fn root_cause(&self) -> &CtsError {
+        match self {
+            CtsError::Stage { source, .. } => source.root_cause(),
+            _ => self,
+        }
+    }
+
+    // process exit code of each failure cause, for scripts calling the tool
+    pub fn exit_code(&self) -> i32 {
+        match self.root_cause() {
+            CtsError::Io { .. } => 2,
+            CtsError::ConfigParse { .. } => 3,
+            CtsError::PluginLoad { .. } => 4,
+            CtsError::PluginSymbol { .. } => 5,
+            CtsError::Login { .. } => 6,
+            CtsError::DesignImport { .. } => 7,
+            CtsError::EmptySinks => 8,
+            CtsError::BadTopology(_) => 9,
+            CtsError::Liberty(_) => 10,
+            CtsError::MissingBufferModel(_) => 11,
+            CtsError::Ga(_) => 12,
+            CtsError::Export(_) => 13,
+            CtsError::Timing(_) => 14,
+            CtsError::Stage { .. } => 1,
+        }
+    }
+
+    pub fn io(path: &str) -> impl FnOnce(std::io::Error) -> CtsError + '_ {
+        move |source| CtsError::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn config(path: &str) -> impl FnOnce(serde_yaml::Error) -> CtsError + '_ {
+        move |e| CtsError::ConfigParse {
+            path: path.to_string(),
+            reason: e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CtsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CtsError::Io { path, source } => write!(f, "failed to access {}: {}", path, source),
+            CtsError::ConfigParse { path, reason } => {
+                write!(f, "failed to parse config {}: {}", path, reason)
+            }
+            CtsError::PluginLoad { path, reason } => {
+                write!(f, "failed to load plugin {}: {}", path, reason)
+            }
+            CtsError::PluginSymbol { symbol, reason } => {
+                write!(f, "failed to find plugin symbol {}: {}", symbol, reason)
+            }
+            CtsError::Login { username, reason } => {
+                write!(f, "failed to login as {}: {}", username, reason)
+            }
+            CtsError::DesignImport { path, reason } => {
+                write!(f, "failed to import design {}: {}", path, reason)
+            }
+            CtsError::EmptySinks => write!(f, "no clock sink is found"),
+            CtsError::BadTopology(reason) => write!(f, "bad topology: {}", reason),
+            CtsError::Liberty(reason) => write!(f, "liberty error: {}", reason),
+            CtsError::MissingBufferModel(buffers) => {
+                write!(f, "buffers not found in liberty: {}", buffers.join(", "))
+            }
+            CtsError::Ga(reason) => write!(f, "buffer insertion GA failed: {}", reason),
+            CtsError::Export(reason) => write!(f, "export failed: {}", reason),
+            CtsError::Timing(reason) => write!(f, "timing analysis failed: {}", reason),
+            CtsError::Stage { stage, source } => write!(f, "{:?} stage: {}", stage, source),
+        }
+    }
+}
+
+impl Error for CtsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CtsError::Io { source, .. } => Some(source),
+            CtsError::Stage { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -1,76 +1,475 @@
 #![allow(dead_code)]
 
+// progress messages shown at verbosity 1 and above
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if crate::verbosity() >= 1 {
+            println!($($arg)*);
+        }
+    };
+}
+
+// detailed messages shown at verbosity 2 and above
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        if crate::verbosity() >= 2 {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod builder;
 mod cfg;
+mod liberty;
 mod merge;
 mod model;
 // mod node;
-mod error;
+pub mod error;
+mod plugin;
 mod stage;
+#[cfg(test)]
+mod test_util;
 
-use crate::cfg::load_design;
-use crate::model::*;
+use crate::cfg::check_plugin_cfg;
+use crate::error::{CtsError, CtsStage};
 
-use stage::cfg::*;
-use std::error::Error;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use stage::export::{export_def, export_verilog};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::result::Result;
+use std::sync::atomic::{AtomicU8, Ordering as AtomicOrdering};
+
+pub use crate::builder::ClockTreeBuilder;
+pub use crate::cfg::{load_design, DesignCfg};
+pub use crate::liberty::Liberty;
+pub use crate::model::{ClockTree, DelayMetric, LayerRc, Location, Sink, WireModel};
+pub use crate::plugin::{DesignPlugin, RoutedNet, WireSegment};
+pub use stage::cfg::{
+    BufferingCfg, ClockCfg, CtsCfg, DummyLoadCfg, ExportCfg, GenTopologyCfg, GroupBalance,
+    GroupingStrategy, LatencyOffsetCfg, MergeEmbedding,
+};
+pub use stage::timing::{SinkTiming, TimingReport};
+
+// what run_symcts_with does with the clock trees
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RunMode {
+    Full,         // buffer, route and time every clock tree, then export them
+    Report,       // same as Full without export
+    TopologyOnly, // stop after topology generation
+}
+
+pub struct RunOptions {
+    pub mode: RunMode,
+    pub output_dir: Option<String>, // DEF and netlist are written here, keeping their file names
+    pub seed: Option<u64>,          // overrides the seed in CTS config
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            mode: RunMode::Full,
+            output_dir: None,
+            seed: None,
+        }
+    }
+}
+
+// verbosity of progress messages, 0 is quiet, 1 reports each stage and 2 adds details
+static VERBOSITY: AtomicU8 = AtomicU8::new(1);
+
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, AtomicOrdering::Relaxed);
+}
+
+pub(crate) fn verbosity() -> u8 {
+    VERBOSITY.load(AtomicOrdering::Relaxed)
+}
 
 pub fn run_symcts(
     design_cfg_path: &str,
     plugin_cfg_path: &str,
     cts_cfg_path: &str,
-) -> Result<(), Box<dyn Error>> {
-    let mut my_design = load_design(plugin_cfg_path, design_cfg_path)?;
-    let cts_cfg: CtsCfg = serde_yaml::from_str(&fs::read_to_string(cts_cfg_path)?)?;
-    let sinks: Vec<(String, (i32, i32))> = my_design.get_clock_sinks("")?;
-    let mut clocktree = ClockTree {
-        name: cts_cfg.name.clone(),
-        ..Default::default()
+) -> Result<(), CtsError> {
+    run_symcts_with(
+        design_cfg_path,
+        plugin_cfg_path,
+        cts_cfg_path,
+        &RunOptions::default(),
+    )
+}
+
+pub fn run_symcts_with(
+    design_cfg_path: &str,
+    plugin_cfg_path: &str,
+    cts_cfg_path: &str,
+    options: &RunOptions,
+) -> Result<(), CtsError> {
+    let load_stage = |e: CtsError| e.in_stage(CtsStage::LoadDesign);
+    let mut cts_cfg = load_cts_cfg(cts_cfg_path)?;
+    if let Some(dir) = options.output_dir.as_ref() {
+        fs::create_dir_all(dir).map_err(CtsError::io(dir))?;
+        let export_cfg = &mut cts_cfg.export_cfg;
+        export_cfg.def_path = output_path(dir, &export_cfg.def_path);
+        export_cfg.verilog_path = output_path(dir, &export_cfg.verilog_path);
+    }
+    let mut my_design = load_design(plugin_cfg_path, design_cfg_path).map_err(load_stage)?;
+    let design_cfg = DesignCfg::new(design_cfg_path).map_err(load_stage)?;
+    let plugin_error = |e: Box<dyn std::error::Error>| CtsError::DesignImport {
+        path: design_cfg_path.to_string(),
+        reason: e.to_string(),
     };
-    let (mut x_min, mut x_max, mut y_min, mut y_max) = (i32::MAX, i32::MIN, i32::MAX, i32::MIN);
-    clocktree.sinks = sinks
-        .iter()
-        .map(|x| {
-            let (sink_x, sink_y) = x.1;
-            // let (offset_x, offset_y) =
-            //     *sink_offset.get(&x.0).and_then(|x| x.get(&orient)).unwrap();
-            // let offset_dbu = (
-            //     (dbu_factor * offset_x) as i32,
-            //     (dbu_factor * offset_y) as i32,
-            // );
-            // // let load_cap = *sink_cap.get(&x.0).unwrap();
-            // let sink_x = origin_x + offset_dbu.0;
-            // let sink_y = origin_y + offset_dbu.1;
-            // let sink_x = origin_x;
-            // let sink_y = origin_y;
-            if sink_x < x_min {
-                x_min = sink_x;
-            }
-            if sink_x > x_max {
-                x_max = sink_x;
+    let die_area = my_design
+        .get_die_area()
+        .map_err(plugin_error)
+        .map_err(load_stage)?;
+    let mut obstacles = my_design
+        .get_blockages()
+        .map_err(plugin_error)
+        .map_err(load_stage)?;
+    obstacles.extend(
+        my_design
+            .get_fixed_macros()
+            .map_err(plugin_error)
+            .map_err(load_stage)?,
+    );
+    info!("obstacles:{}", obstacles.len());
+    let seed = options.seed.or(cts_cfg.seed).unwrap_or_else(rand::random);
+    info!("seed:{}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let liberties = load_liberties(&cts_cfg.clocks).map_err(load_stage)?;
+
+    // generated clock trees are placed before their parent tree
+    let mut clocktrees = vec![];
+    let mut visited = HashSet::new();
+    for clock_cfg in &cts_cfg.clocks {
+        visited.insert(clock_cfg.name.clone());
+    }
+    let mut design = ClockDesign {
+        plugin: my_design.as_mut(),
+        design_cfg_path,
+        die_area,
+        obstacles: &obstacles,
+        export_cfg: &cts_cfg.export_cfg,
+        mode: options.mode,
+        seed,
+    };
+    for clock_cfg in &cts_cfg.clocks {
+        synthesize_hierarchy(
+            &clock_cfg.name,
+            clock_cfg,
+            &liberties[clock_cfg.stage2_cfg.liberty_paths.as_slice()],
+            &mut design,
+            &mut visited,
+            &mut clocktrees,
+            &mut rng,
+        )?;
+    }
+    if options.mode != RunMode::Full {
+        return Ok(());
+    }
+
+    // exporting result
+    let export_stage =
+        |e: Box<dyn std::error::Error>| CtsError::Export(e.to_string()).in_stage(CtsStage::Export);
+    export_def(&clocktrees, my_design.as_mut(), &cts_cfg.export_cfg).map_err(export_stage)?;
+    export_verilog(&clocktrees, &design_cfg.verilog_path, &cts_cfg.export_cfg)
+        .map_err(export_stage)?;
+
+    Ok(())
+}
+
+// Check the config files without loading the plugin or synthesizing: design and plugin
+// config must point to existing files, and every clock must have its branch bounds, GA
+// parameters, offset regexes, liberty, buffers, routing layers and dummy load cell available.
+pub fn validate_config(
+    design_cfg_path: &str,
+    plugin_cfg_path: &str,
+    cts_cfg_path: &str,
+) -> Result<(), CtsError> {
+    let design_cfg = DesignCfg::new(design_cfg_path)?;
+    for path in [&design_cfg.verilog_path, &design_cfg.def_path] {
+        fs::metadata(path).map_err(CtsError::io(path))?;
+    }
+    check_plugin_cfg(plugin_cfg_path)?;
+    let cts_cfg = load_cts_cfg(cts_cfg_path)?;
+    let export_cfg = &cts_cfg.export_cfg;
+    let liberties = load_liberties(&cts_cfg.clocks)?;
+    for clock_cfg in &cts_cfg.clocks {
+        let in_clock = |e: CtsError| CtsError::ConfigParse {
+            path: cts_cfg_path.to_string(),
+            reason: format!("clock {}: {}", clock_cfg.name, e),
+        };
+        clock_cfg.stage1_cfg.check().map_err(in_clock)?;
+        clock_cfg.get_latency_offsets()?;
+        let buffering_cfg = &clock_cfg.stage2_cfg;
+        buffering_cfg.check().map_err(in_clock)?;
+        let liberty = &liberties[buffering_cfg.liberty_paths.as_slice()];
+        liberty
+            .get_buffer_lib(&buffering_cfg.buffer_list)
+            .map_err(in_clock)?;
+        WireModel::new(
+            &buffering_cfg.rho_matrix_path,
+            &export_cfg.horizontal_layer,
+            &export_cfg.vertical_layer,
+        )
+        .map_err(|e| CtsError::ConfigParse {
+            path: buffering_cfg.rho_matrix_path.clone(),
+            reason: e.to_string(),
+        })?;
+        if let Some(dummy_load) = export_cfg.dummy_load.as_ref() {
+            if liberty
+                .get_pin_cap(&dummy_load.cell, &dummy_load.input_pin)
+                .is_none()
+            {
+                return Err(in_clock(CtsError::Liberty(format!(
+                    "pin {} of dummy load {} is not found",
+                    dummy_load.input_pin, dummy_load.cell
+                ))));
             }
-            if sink_y < y_min {
-                y_min = sink_y;
+        }
+    }
+    Ok(())
+}
+
+fn load_cts_cfg(cts_cfg_path: &str) -> Result<CtsCfg, CtsError> {
+    let cts_cfg_str = fs::read_to_string(cts_cfg_path).map_err(CtsError::io(cts_cfg_path))?;
+    let cts_cfg: CtsCfg =
+        serde_yaml::from_str(&cts_cfg_str).map_err(CtsError::config(cts_cfg_path))?;
+    let mut clock_names: Vec<&str> = cts_cfg.clocks.iter().map(|c| c.name.as_str()).collect();
+    clock_names.sort_unstable();
+    clock_names.dedup();
+    if clock_names.is_empty() || clock_names.len() != cts_cfg.clocks.len() {
+        return Err(CtsError::ConfigParse {
+            path: cts_cfg_path.to_string(),
+            reason: "clocks must be non-empty with unique names".to_string(),
+        });
+    }
+    Ok(cts_cfg)
+}
+
+// liberty of each clock, loaded once for clocks with the same liberty paths
+fn load_liberties(clocks: &[ClockCfg]) -> Result<HashMap<&[String], Liberty>, CtsError> {
+    let mut liberties = HashMap::new();
+    for clock_cfg in clocks {
+        let paths = clock_cfg.stage2_cfg.liberty_paths.as_slice();
+        if !liberties.contains_key(paths) {
+            let liberty = Liberty::new(paths).map_err(|e| CtsError::Liberty(e.to_string()))?;
+            liberties.insert(paths, liberty);
+        }
+    }
+    Ok(liberties)
+}
+
+// `path` moved into `dir` with its file name
+fn output_path(dir: &str, path: &str) -> String {
+    let file_name = std::path::Path::new(path)
+        .file_name()
+        .map_or_else(|| path.into(), |d| d.to_os_string());
+    std::path::Path::new(dir)
+        .join(file_name)
+        .display()
+        .to_string()
+}
+
+// design data shared by the synthesis of every clock
+struct ClockDesign<'a> {
+    plugin: &'a mut dyn DesignPlugin,
+    design_cfg_path: &'a str,
+    die_area: Option<(Location, Location)>,
+    obstacles: &'a [(Location, Location)], // blockages and fixed macros
+    export_cfg: &'a ExportCfg,
+    mode: RunMode,
+    seed: u64, // recorded in the timing report of every clock
+}
+
+// Synthesize the clock tree of `net` and the generated clock trees below it, return the
+// insertion delay of the tree. A sink driving another clock net through its output pin,
+// such as clock gating cell or divider flop, is the root of a generated clock tree which
+// is synthesized first with the same setting, and its insertion delay is taken as latency
+// of the sink when balancing the parent tree. An output net is a generated clock when it
+// reaches a clock pin in liberty. The latency is balanced by Elmore DME and by the wire
+// planned for skew after buffering.
+fn synthesize_hierarchy(
+    net: &str,
+    clock_cfg: &ClockCfg,
+    liberty: &Liberty,
+    design: &mut ClockDesign,
+    visited: &mut HashSet<String>,
+    clocktrees: &mut Vec<ClockTree>,
+    rng: &mut StdRng,
+) -> Result<f32, CtsError> {
+    info!("Synthesize clock {}", net);
+    let design_cfg_path = design.design_cfg_path;
+    let plugin_error = |e: Box<dyn std::error::Error>| {
+        CtsError::DesignImport {
+            path: design_cfg_path.to_string(),
+            reason: e.to_string(),
+        }
+        .in_stage(CtsStage::LoadDesign)
+    };
+    let sinks: Vec<(String, (i32, i32))> =
+        design.plugin.get_clock_sinks(net).map_err(plugin_error)?;
+    if sinks.is_empty() {
+        return Err(CtsError::EmptySinks.in_stage(CtsStage::LoadDesign));
+    }
+
+    let mut sink_latency: HashMap<String, f32> = HashMap::new();
+    for (inst, _) in &sinks {
+        let cell = match design.plugin.get_cell_master(inst).map_err(plugin_error)? {
+            Some(cell) => cell,
+            None => continue,
+        };
+        for pin in liberty.get_output_pins(&cell) {
+            let child_net = match design.plugin.get_pin_net(inst, pin).map_err(plugin_error)? {
+                Some(child_net) => child_net,
+                None => continue,
+            };
+            if visited.contains(&child_net) {
+                continue;
             }
-            if sink_y > y_max {
-                y_max = sink_y;
+            let loads = design
+                .plugin
+                .get_net_pins(&child_net)
+                .map_err(plugin_error)?;
+            let mut drives_clock_pin = false;
+            for (load, pin) in &loads {
+                let load_cell = design.plugin.get_cell_master(load).map_err(plugin_error)?;
+                if load_cell.is_some_and(|cell| liberty.is_clock_pin(&cell, pin)) {
+                    drives_clock_pin = true;
+                    break;
+                }
             }
-            Sink {
-                name: x.0.to_string(),
-                location: (sink_x, sink_y),
+            if !drives_clock_pin {
+                continue;
             }
-        })
-        .collect();
-    clocktree.x_range = (x_min, x_max);
-    clocktree.y_range = (y_min, y_max);
-    println!("x_range:{:?}", clocktree.x_range);
-    println!("y_range:{:?}", clocktree.y_range);
-    println!("Load CTS related data successfully");
+            visited.insert(child_net.clone());
+            info!("Generated clock {} is driven by {}", child_net, inst);
+            let latency = synthesize_hierarchy(
+                &child_net, clock_cfg, liberty, design, visited, clocktrees, rng,
+            )?;
+            let offset = sink_latency.entry(inst.clone()).or_insert(0.0);
+            *offset = offset.max(latency);
+        }
+    }
 
-    clocktree.gen_topology(&cts_cfg.stage1_cfg);
-    clocktree.buffering(&cts_cfg.stage2_cfg);
+    let (clocktree, latency) =
+        synthesize_clock(net, clock_cfg, &sinks, &sink_latency, design, liberty, rng)?;
+    clocktrees.push(clocktree);
+    Ok(latency)
+}
 
-    // exporting result
+// build, buffer, route and report the clock tree of one clock net, return the tree and its
+// insertion delay
+fn synthesize_clock(
+    net: &str,
+    clock_cfg: &ClockCfg,
+    sinks: &[(String, (i32, i32))],
+    sink_latency: &HashMap<String, f32>,
+    design: &ClockDesign,
+    liberty: &Liberty,
+    rng: &mut StdRng,
+) -> Result<(ClockTree, f32), CtsError> {
+    let offsets = clock_cfg
+        .get_latency_offsets()
+        .map_err(|e| e.in_stage(CtsStage::LoadDesign))?;
+    let plugin_error = |e: Box<dyn std::error::Error>| {
+        CtsError::DesignImport {
+            path: design.design_cfg_path.to_string(),
+            reason: e.to_string(),
+        }
+        .in_stage(CtsStage::LoadDesign)
+    };
+    let mut tree_sinks = vec![];
+    for (inst, location) in sinks {
+        let cell = design
+            .plugin
+            .get_cell_master(inst)
+            .map_err(plugin_error)?
+            .ok_or_else(|| {
+                CtsError::Liberty(format!("master of sink {} is not found", inst))
+                    .in_stage(CtsStage::LoadDesign)
+            })?;
+        let cap = liberty.get_clock_pin_cap(&cell).ok_or_else(|| {
+            CtsError::Liberty(format!(
+                "clock pin capacitance of sink {} ({}) is not found",
+                inst, cell
+            ))
+            .in_stage(CtsStage::LoadDesign)
+        })?;
+        tree_sinks.push(Sink {
+            name: inst.to_string(),
+            location: *location,
+            cap,
+            latency: sink_latency.get(inst).cloned().unwrap_or(0.0),
+            offset: offsets.get(inst),
+            dummy: false,
+        });
+    }
+    let mut builder = ClockTree::builder(net).sinks(tree_sinks);
+    // generated clock is driven by its root sink instead of a top level pin
+    if let Some(source) = design.plugin.get_pin_location(net).map_err(plugin_error)? {
+        builder = builder.source(source);
+    }
+    if let Some((lower_left, upper_right)) = design.die_area {
+        builder = builder.die_area(lower_left, upper_right);
+    }
+    let mut clocktree = builder
+        .obstacles(design.obstacles.iter().cloned())
+        .build()
+        .map_err(|e| e.in_stage(CtsStage::LoadDesign))?;
+    detail!("x_range:{:?}", clocktree.x_range);
+    detail!("y_range:{:?}", clocktree.y_range);
+    detail!("Load CTS related data successfully");
 
-    Ok(())
+    if design.mode == RunMode::TopologyOnly {
+        clocktree
+            .gen_topology(&clock_cfg.stage1_cfg)
+            .map_err(|e| e.in_stage(CtsStage::GenTopology))?;
+        // wire model is not loaded, Elmore DME falls back to path length
+        if let Some(MergeEmbedding::Dme) | Some(MergeEmbedding::ElmoreDme) =
+            clock_cfg.stage1_cfg.merging
+        {
+            clocktree
+                .embed_dme(None)
+                .map_err(|e| e.in_stage(CtsStage::GenTopology))?;
+        }
+        clocktree.legalize();
+        return Ok((clocktree, 0.0));
+    }
+    let export_cfg = design.export_cfg;
+    let wire = WireModel::new(
+        &clock_cfg.stage2_cfg.rho_matrix_path,
+        &export_cfg.horizontal_layer,
+        &export_cfg.vertical_layer,
+    )
+    .map_err(|e| CtsError::ConfigParse {
+        path: clock_cfg.stage2_cfg.rho_matrix_path.clone(),
+        reason: e.to_string(),
+    })
+    .map_err(|e| e.in_stage(CtsStage::Buffering))?;
+    let report = clocktree.synthesize(
+        clock_cfg,
+        liberty,
+        &wire,
+        export_cfg.dummy_load.as_ref(),
+        rng,
+    )?;
+    let latency = match report {
+        Some(mut report) => {
+            report.seed = Some(design.seed);
+            info!("timing report of clock {}\n{}", clocktree.name, report);
+            report.max_latency
+        }
+        None => {
+            return Err(CtsError::Timing(format!(
+                "clock {} has a buffer without timing model or no sink",
+                clocktree.name
+            ))
+            .in_stage(CtsStage::Timing))
+        }
+    };
+
+    Ok((clocktree, latency))
 }
@@ -0,0 +1,497 @@
+// read timing data of buffer and sink cells from liberty(.lib) file
+// Note: only the subset used by CTS is extracted, values keep the units of the library
+use crate::error::CtsError;
+use crate::model::{BufferLib, BufferModel, LutModel};
+use std::collections::HashMap;
+use std::error::Error;
+
+// liberty group such as `cell (BUF_X1) { ... }`
+#[derive(Default, Debug)]
+struct Group {
+    kind: String,
+    args: Vec<String>,
+    attributes: Vec<(String, Vec<String>)>, // simple attribute has exactly one value
+    groups: Vec<Group>,
+}
+
+impl Group {
+    fn get_attribute(&self, name: &str) -> Option<&Vec<String>> {
+        self.attributes.iter().find(|a| a.0 == name).map(|a| &a.1)
+    }
+    fn get_str(&self, name: &str) -> Option<&str> {
+        self.get_attribute(name)
+            .and_then(|v| v.first())
+            .map(|v| v.as_str())
+    }
+    fn get_f32(&self, name: &str) -> Option<f32> {
+        self.get_str(name).and_then(|v| v.parse().ok())
+    }
+    fn sub_groups<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a Group> + 'a {
+        self.groups.iter().filter(move |g| g.kind == kind)
+    }
+}
+
+#[derive(Default)]
+pub struct Liberty {
+    templates: HashMap<String, Group>, // lu_table_template
+    cells: HashMap<String, Group>,
+}
+
+impl Liberty {
+    pub fn new<P: AsRef<std::path::Path>>(paths: &[P]) -> Result<Liberty, Box<dyn Error>> {
+        let mut liberty = Liberty::default();
+        for path in paths {
+            let lib_str = std::fs::read_to_string(path)?;
+            liberty.load_str(&lib_str)?;
+        }
+        detail!("Load {} cells from liberty", liberty.cells.len());
+        Ok(liberty)
+    }
+
+    pub fn load_str(&mut self, lib_str: &str) -> Result<(), Box<dyn Error>> {
+        let tokens = tokenize(lib_str);
+        let mut root = Group::default();
+        let mut pos = 0;
+        while pos < tokens.len() {
+            parse_item(&tokens, &mut pos, &mut root)?;
+        }
+        for library in root.groups.into_iter().filter(|g| g.kind == "library") {
+            for g in library.groups {
+                let name = match g.args.first() {
+                    Some(name) => name.clone(),
+                    None => continue,
+                };
+                match g.kind.as_str() {
+                    "lu_table_template" => {
+                        self.templates.insert(name, g);
+                    }
+                    "cell" => {
+                        self.cells.insert(name, g);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_cell(&self, cell: &str) -> bool {
+        self.cells.contains_key(cell)
+    }
+
+    // clock pin of the cell, pin with `clock : true`, or `clock_gate_clock_pin : true` in a
+    // clock gating cell
+    fn get_clock_pin(&self, cell: &str) -> Option<&Group> {
+        self.cells.get(cell)?.sub_groups("pin").find(|p| {
+            p.get_str("clock") == Some("true") || p.get_str("clock_gate_clock_pin") == Some("true")
+        })
+    }
+
+    pub fn get_clock_pin_cap(&self, cell: &str) -> Option<f32> {
+        self.get_clock_pin(cell).and_then(get_pin_cap)
+    }
+
+    pub fn is_clock_pin(&self, cell: &str, pin: &str) -> bool {
+        self.get_clock_pin(cell)
+            .is_some_and(|p| p.args.first().map(|s| s.as_str()) == Some(pin))
+    }
+
+    pub fn get_pin_cap(&self, cell: &str, pin: &str) -> Option<f32> {
+        self.cells
+            .get(cell)?
+            .sub_groups("pin")
+            .find(|p| p.args.first().map(|s| s.as_str()) == Some(pin))
+            .and_then(get_pin_cap)
+    }
+
+    // output pins of the cell, such as Q of a flop or GCLK of a clock gating cell
+    pub fn get_output_pins(&self, cell: &str) -> Vec<&str> {
+        self.cells
+            .get(cell)
+            .map(|c| {
+                c.sub_groups("pin")
+                    .filter(|p| p.get_str("direction") == Some("output"))
+                    .filter_map(|p| p.args.first().map(|s| s.as_str()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // input pin capacitance, output pin max capacitance, delay and transition tables of a buffer
+    pub fn get_buffer_model(&self, cell: &str) -> Result<BufferModel, Box<dyn Error>> {
+        let cell_group = self
+            .cells
+            .get(cell)
+            .ok_or(format!("cell {} is not found in liberty", cell))?;
+        let input_pin = cell_group
+            .sub_groups("pin")
+            .find(|p| p.get_str("direction") == Some("input"))
+            .ok_or(format!("no input pin in cell {}", cell))?;
+        let output_pin = cell_group
+            .sub_groups("pin")
+            .find(|p| p.get_str("direction") == Some("output"))
+            .ok_or(format!("no output pin in cell {}", cell))?;
+        let timing = output_pin
+            .sub_groups("timing")
+            .find(|t| t.get_str("related_pin") == input_pin.args.first().map(|s| s.as_str()))
+            .ok_or(format!(
+                "no timing arc from input to output in cell {}",
+                cell
+            ))?;
+        let get_table = |kind: &str| -> Result<LutModel, Box<dyn Error>> {
+            let table = timing
+                .sub_groups(kind)
+                .next()
+                .ok_or(format!("no {} table in cell {}", kind, cell))?;
+            self.get_lut(table)
+        };
+        Ok(BufferModel {
+            area: cell_group.get_f32("area").unwrap_or(0.0),
+            input_cap: get_pin_cap(input_pin).unwrap_or(0.0),
+            max_cap: output_pin.get_f32("max_capacitance"),
+            cell_rise: get_table("cell_rise")?,
+            cell_fall: get_table("cell_fall")?,
+            rise_transition: get_table("rise_transition")?,
+            fall_transition: get_table("fall_transition")?,
+        })
+    }
+
+    // buffer library in the order of `buffer_list`, every buffer must be found in liberty
+    pub fn get_buffer_lib(&self, buffer_list: &[String]) -> Result<BufferLib, CtsError> {
+        let missing: Vec<String> = buffer_list
+            .iter()
+            .filter(|b| !self.has_cell(b))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(CtsError::MissingBufferModel(missing));
+        }
+        let mut buffers = vec![];
+        for buffer in buffer_list {
+            let model = self
+                .get_buffer_model(buffer)
+                .map_err(|e| CtsError::Liberty(e.to_string()))?;
+            buffers.push((buffer.clone(), model));
+        }
+        BufferLib::new(buffers).map_err(|e| CtsError::Liberty(e.to_string()))
+    }
+
+    // Table index is taken from the table itself or from its template.
+    // LutModel always has input slew as index_1, so table indexed by load capacitance
+    // first is transposed, and one dimensional table gets a single point on the missing index.
+    fn get_lut(&self, table: &Group) -> Result<LutModel, Box<dyn Error>> {
+        let template = table.args.first().and_then(|t| self.templates.get(t));
+        let get_index = |name: &str| -> Option<Vec<f32>> {
+            table
+                .get_attribute(name)
+                .or_else(|| template.and_then(|t| t.get_attribute(name)))
+                .map(|v| v.iter().flat_map(|d| parse_numbers(d)).collect())
+        };
+        let variable_1 = template
+            .and_then(|t| t.get_str("variable_1"))
+            .unwrap_or("input_net_transition");
+        let values: Vec<Vec<f32>> = table
+            .get_attribute("values")
+            .ok_or("no values in look up table")?
+            .iter()
+            .map(|d| parse_numbers(d))
+            .collect();
+        let index_1 = get_index("index_1").unwrap_or_else(|| vec![0.0]);
+        let index_2 = get_index("index_2");
+
+        let (slew_index, cap_index, values) = match index_2 {
+            // values of 2-D table are given row by row of index_1
+            Some(index_2) => {
+                if values.iter().any(|row| row.len() != index_2.len()) {
+                    return Err("look up table value doesn't match index_2 size".into());
+                }
+                if variable_1.contains("capacitance") {
+                    let transposed = (0..index_2.len())
+                        .map(|j| values.iter().map(|row| row[j]).collect())
+                        .collect();
+                    (index_2, index_1, transposed)
+                } else {
+                    (index_1, index_2, values)
+                }
+            }
+            // values of 1-D table are given as one row
+            None => {
+                if variable_1.contains("capacitance") {
+                    (vec![0.0], index_1, values)
+                } else {
+                    let column = values.concat().into_iter().map(|d| vec![d]).collect();
+                    (index_1, vec![0.0], column)
+                }
+            }
+        };
+        LutModel::new(slew_index, cap_index, values)
+    }
+}
+
+fn get_pin_cap(pin: &Group) -> Option<f32> {
+    match (
+        pin.get_f32("rise_capacitance"),
+        pin.get_f32("fall_capacitance"),
+    ) {
+        (Some(rise), Some(fall)) => Some(rise.max(fall)),
+        _ => pin.get_f32("capacitance"),
+    }
+}
+
+fn parse_numbers(s: &str) -> Vec<f32> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|d| d.parse().ok())
+        .collect()
+}
+
+// split liberty text into words, quoted strings and punctuations `(){}:;,`.
+// Comments and line continuation are dropped, quotes are removed from strings.
+fn tokenize(s: &str) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() || c == '\\' {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '"' {
+            let mut word = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] != '\\' && chars[i] != '\n' && chars[i] != '\r' {
+                    word.push(chars[i]);
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(word);
+        } else if "(){}:;,".contains(c) {
+            tokens.push(c.to_string());
+            i += 1;
+        } else {
+            let mut word = String::new();
+            while i < chars.len() && !chars[i].is_whitespace() && !"(){}:;,\"\\".contains(chars[i])
+            {
+                word.push(chars[i]);
+                i += 1;
+            }
+            tokens.push(word);
+        }
+    }
+    tokens
+}
+
+// parse one attribute or group at `pos` into `parent`
+fn parse_item(
+    tokens: &[String],
+    pos: &mut usize,
+    parent: &mut Group,
+) -> Result<(), Box<dyn Error>> {
+    let name = tokens[*pos].clone();
+    *pos += 1;
+    match tokens.get(*pos).map(|t| t.as_str()) {
+        // simple attribute
+        Some(":") => {
+            *pos += 1;
+            let mut value = vec![];
+            while *pos < tokens.len() && tokens[*pos] != ";" && tokens[*pos] != "}" {
+                value.push(tokens[*pos].clone());
+                *pos += 1;
+            }
+            if tokens.get(*pos).map(|t| t.as_str()) == Some(";") {
+                *pos += 1;
+            }
+            parent.attributes.push((name, vec![value.join(" ")]));
+        }
+        // complex attribute or group
+        Some("(") => {
+            *pos += 1;
+            let mut args = vec![];
+            while *pos < tokens.len() && tokens[*pos] != ")" {
+                if tokens[*pos] != "," {
+                    args.push(tokens[*pos].clone());
+                }
+                *pos += 1;
+            }
+            *pos += 1;
+            if tokens.get(*pos).map(|t| t.as_str()) == Some("{") {
+                *pos += 1;
+                let mut group = Group {
+                    kind: name,
+                    args,
+                    ..Default::default()
+                };
+                while *pos < tokens.len() && tokens[*pos] != "}" {
+                    parse_item(tokens, pos, &mut group)?;
+                }
+                if *pos >= tokens.len() {
+                    return Err(format!("unclosed liberty group {}", group.kind).into());
+                }
+                *pos += 1;
+                parent.groups.push(group);
+            } else {
+                if tokens.get(*pos).map(|t| t.as_str()) == Some(";") {
+                    *pos += 1;
+                }
+                parent.attributes.push((name, args));
+            }
+        }
+        // stray token such as an extra `;`
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBERTY: &str = r#"
+library (test) {
+  cell (DFF) {
+    pin (D) { direction : input ; capacitance : 0.001 ; }
+    pin (CK) { direction : input ; clock : true ; capacitance : 0.002 ; }
+    pin (Q) { direction : output ; }
+  }
+  cell (ICG) {
+    pin (E) { direction : input ; clock_gate_enable_pin : true ; capacitance : 0.001 ; }
+    pin (CK) { direction : input ; clock_gate_clock_pin : true ; capacitance : 0.003 ; }
+    pin (GCK) { direction : output ; clock_gate_out_pin : true ; }
+  }
+  cell (AND2) {
+    pin (A) { direction : input ; capacitance : 0.001 ; }
+  }
+}
+"#;
+
+    #[test]
+    fn clock_pin_cap() {
+        let mut liberty = Liberty::default();
+        liberty.load_str(LIBERTY).unwrap();
+        assert_eq!(liberty.get_clock_pin_cap("DFF"), Some(0.002));
+        assert_eq!(liberty.get_clock_pin_cap("ICG"), Some(0.003));
+        assert_eq!(liberty.get_clock_pin_cap("AND2"), None);
+        assert_eq!(liberty.get_clock_pin_cap("NAND2"), None);
+        assert!(liberty.is_clock_pin("ICG", "CK"));
+        assert!(!liberty.is_clock_pin("ICG", "E"));
+        assert!(!liberty.is_clock_pin("DFF", "D"));
+    }
+
+    const BUFFERS: &str = r#"
+library (buffers) {
+  lu_table_template (slew_cap) {
+    variable_1 : input_net_transition ;
+    variable_2 : total_output_net_capacitance ;
+    index_1 ("0.1, 0.3");
+    index_2 ("0.01, 0.02");
+  }
+  lu_table_template (cap_slew) {
+    variable_1 : total_output_net_capacitance ;
+    variable_2 : input_net_transition ;
+    index_1 ("0.01, 0.02");
+    index_2 ("0.1, 0.3");
+  }
+  cell (BUF_X1) {
+    area : 2.0 ;
+    pin (A) { direction : input ; rise_capacitance : 0.002 ; fall_capacitance : 0.003 ; }
+    pin (Z) { direction : output ; max_capacitance : 0.2 ; function : "A" ;
+      timing () { related_pin : "A" ;
+        cell_rise (slew_cap) { values ("1.0, 2.0", "3.0, 4.0"); }
+        cell_fall (slew_cap) { values ("1.5, 1.0", "2.0, 5.0"); }
+        rise_transition (slew_cap) { values ("0.1, 0.2", "0.3, 0.4"); }
+        fall_transition (slew_cap) { values ("0.2, 0.1", "0.3, 0.3"); }
+      }
+    }
+  }
+  cell (BUF_X2) {
+    area : 3.0 ;
+    pin (A) { direction : input ; capacitance : 0.004 ; }
+    pin (Z) { direction : output ; function : "A" ;
+      timing () { related_pin : "A" ;
+        cell_rise (cap_slew) { values ("1.0, 3.0", "2.0, 4.0"); }
+        cell_fall (cap_slew) { values ("1.0, 3.0", "2.0, 4.0"); }
+        rise_transition (cap_slew) { values ("0.1, 0.3", "0.2, 0.4"); }
+        fall_transition (cap_slew) { values ("0.1, 0.3", "0.2, 0.4"); }
+      }
+    }
+  }
+}
+"#;
+
+    fn load_buffers() -> Liberty {
+        let mut liberty = Liberty::default();
+        liberty.load_str(BUFFERS).unwrap();
+        liberty.load_str(LIBERTY).unwrap();
+        liberty
+    }
+
+    #[test]
+    fn buffer_model_takes_worse_edge() {
+        let liberty = load_buffers();
+        let model = liberty.get_buffer_model("BUF_X1").unwrap();
+        assert_eq!(model.area, 2.0);
+        assert_eq!(model.input_cap, 0.003);
+        assert_eq!(model.max_cap, Some(0.2));
+        assert_eq!(model.get_delay(0.1, 0.01), 1.5);
+        assert_eq!(model.get_delay(0.1, 0.02), 2.0);
+        assert_eq!(model.get_delay(0.3, 0.02), 5.0);
+        assert_eq!(model.get_transition(0.1, 0.01), 0.2);
+        assert_eq!(model.get_transition(0.3, 0.02), 0.4);
+        // DFF has no timing arc from input to output
+        assert!(liberty.get_buffer_model("DFF").is_err());
+        assert!(liberty.get_buffer_model("BUF_X4").is_err());
+    }
+
+    #[test]
+    fn lut_indexed_by_capacitance_first() {
+        let liberty = load_buffers();
+        let by_slew = liberty.get_buffer_model("BUF_X1").unwrap();
+        let by_cap = liberty.get_buffer_model("BUF_X2").unwrap();
+        assert_eq!(by_cap.input_cap, 0.004);
+        assert_eq!(by_cap.max_cap, None);
+        for slew in [0.1, 0.2, 0.3] {
+            for cap in [0.01, 0.015, 0.02] {
+                assert_eq!(
+                    by_cap.cell_rise.get_value(slew, cap),
+                    by_slew.cell_rise.get_value(slew, cap)
+                );
+                assert_eq!(
+                    by_cap.rise_transition.get_value(slew, cap),
+                    by_slew.rise_transition.get_value(slew, cap)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn buffer_lib_follows_buffer_list() {
+        let liberty = load_buffers();
+        let list = ["BUF_X2".to_string(), "BUF_X1".to_string()];
+        let buffer_lib = liberty.get_buffer_lib(&list).unwrap();
+        assert_eq!(buffer_lib.max_gene(), 2);
+        assert!(buffer_lib.get(0).is_none());
+        assert_eq!(buffer_lib.get(1).unwrap().0, "BUF_X2");
+        assert_eq!(buffer_lib.get(2).unwrap().0, "BUF_X1");
+        assert_eq!(buffer_lib.get_model("BUF_X1").unwrap().input_cap, 0.003);
+
+        let missing = ["BUF_X1".to_string(), "BUF_X4".to_string()];
+        assert!(matches!(
+            liberty.get_buffer_lib(&missing),
+            Err(CtsError::MissingBufferModel(cells)) if cells == ["BUF_X4"]
+        ));
+        assert!(matches!(
+            liberty.get_buffer_lib(&["DFF".to_string()]),
+            Err(CtsError::Liberty(_))
+        ));
+    }
+}
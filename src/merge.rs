@@ -144,70 +144,4 @@ impl MergeUnit {
             }
         }
     }
-
-    //     pub fn merge(&mut self) {
-    //         if self.if_horizontal {
-    //             for x in &self.sink {
-    //                 if x.0 == self.x_range.0 || x.0 == self.x_range.1 {
-    //                     let turn = (x.0,self.root.1);
-    //                     self.path.push(Path{from:*x,turn:Some(turn),to:self.root});
-    //                 } else {
-    //                     let sink2root = ((x.0 - self.root.0).abs() + (x.1 - self.root.1).abs()) as u32;
-    //                     let delta = self.common_length as i32 - sink2root as i32;
-
-    //                     let mut end = x.clone();
-    //                     if delta > 0 {
-    //                         let extra = delta/2;
-    //                         if x.0 <= self.root.0 {
-    //                             end.0 -= extra as i32;
-    //                             end.1 = self.root.1;
-    //                         } else {
-    //                             end.0 += extra as i32;
-    //                             end.1 = self.root.1;
-    //                         }
-    //                         let turn = (end.0,x.1);
-    //                         self.path.push(Path{from:*x,turn:Some(turn),to:end});
-    //                     } else {
-    //                         // prepare for new path
-    //                         let start = end;
-    //                         let end = (start.0,self.root.1);
-    //                         self.path.push(Path{from:start,turn:None,to:end});
-    //                     }
-
-    //                 }
-    //             }
-
-    //         }
-    //         else {
-    //             for x in &self.sink {
-    //                 if x.1 == self.y_range.0 || x.1 == self.y_range.1 {
-    //                     let turn = (self.root.0,x.1);
-    //                     self.path.push(Path{from:*x,turn:Some(turn),to:self.root});
-    //                 } else {
-    //                     let sink2root = ((x.0 - self.root.0).abs() + (x.1 - self.root.1).abs()) as u32;
-    //                     let delta = self.common_length as i32 - sink2root as i32;
-    //                     let mut end = x.clone();
-    //                     if delta > 0 {
-    //                         let extra = delta/2;
-    //                         if x.1 <= self.root.1 {
-    //                             end.1 -= extra as i32;
-    //                             end.0 = self.root.0;
-    //                         } else {
-    //                             end.1 += extra as i32;
-    //                             end.0 = self.root.0;
-    //                         }
-    //                         let turn = (x.0,end.1);
-    //                         self.path.push(Path{from:*x,turn:Some(turn),to:end});
-    //                     } else {
-    //                         let start = end;
-    //                         let end = (self.root.0,start.1);
-    //                         self.path.push(Path{from:start,turn:None,to:end});
-    //                     }
-
-    //                 }
-    //             }
-
-    //         }
-
-    // }
 }
@@ -0,0 +1,90 @@
+// structural checks of clock tree built by gen_topology
+use super::*;
+use crate::error::CtsError;
+
+impl ClockTree {
+    /// Verify the tree from its root
+    ///
+    /// The root node must be set and drive a merge unit. Every child of a non-sink node is
+    /// reached through NodeSuccessor exactly once, every sink is reached exactly once, and
+    /// no node or merge unit is left outside the tree.
+    pub fn check_consistency(&self) -> Result<(), CtsError> {
+        let bad = |reason: String| Err(CtsError::BadTopology(reason));
+        if self.root_node_index >= self.nodes.len() {
+            return bad(format!("root node {} is not set", self.root_node_index));
+        }
+        if self.get_node_merge_unit(self.root_node_index).is_none() {
+            return bad("root node drives no merge unit".to_string());
+        }
+        let mut node_visited = vec![false; self.nodes.len()];
+        let mut merge_visited = vec![false; self.merges.len()];
+        let mut sink_count = vec![0; self.sinks.len()];
+        // (node, level of the merge unit it drives)
+        let mut stack = vec![(self.root_node_index, 0)];
+        node_visited[self.root_node_index] = true;
+        while let Some((nidx, level)) = stack.pop() {
+            let midx = match self.nodes[nidx].node_owner {
+                NodeOwner::Sink(d) => {
+                    match sink_count.get_mut(d) {
+                        Some(count) => *count += 1,
+                        None => return bad(format!("node {} owns unknown sink {}", nidx, d)),
+                    }
+                    if level != self.tree_level {
+                        return bad(format!("sink {} is at level {}", d, level));
+                    }
+                    continue;
+                }
+                NodeOwner::Buffer(d) => match self.buffers.get(d) {
+                    Some(buffer) => buffer.load,
+                    None => return bad(format!("node {} owns unknown buffer {}", nidx, d)),
+                },
+                NodeOwner::MergeUnit(d) => d,
+            };
+            match merge_visited.get_mut(midx) {
+                Some(true) => return bad(format!("merge unit {} has more than one driver", midx)),
+                Some(visited) => *visited = true,
+                None => return bad(format!("node {} drives unknown merge unit {}", nidx, midx)),
+            }
+            if level >= self.tree_level {
+                return bad(format!("merge unit {} is below the lowest level", midx));
+            }
+            // walk the childs by hand, so that a loop of next_node is caught
+            let mut fanout: u32 = 0;
+            let mut next = Some(self.merges[midx].first_node);
+            while let Some(child) = next {
+                match node_visited.get_mut(child) {
+                    Some(true) => {
+                        return bad(format!("node {} is reached more than once", child));
+                    }
+                    Some(visited) => *visited = true,
+                    None => return bad(format!("merge unit {} has unknown child {}", midx, child)),
+                }
+                fanout += 1;
+                stack.push((child, level + 1));
+                next = self.nodes[child].next_node;
+            }
+            if self.fanout_map.get(&level) != Some(&fanout) {
+                return bad(format!(
+                    "merge unit {} has {} childs, fanout of level {} is {:?}",
+                    midx,
+                    fanout,
+                    level,
+                    self.fanout_map.get(&level)
+                ));
+            }
+        }
+        if let Some(d) = sink_count.iter().position(|count| *count != 1) {
+            return bad(format!(
+                "sink {} appears {} times in tree",
+                self.sinks[d].name, sink_count[d]
+            ));
+        }
+        if let Some(d) = node_visited.iter().position(|visited| !visited) {
+            return bad(format!("node {} is not reachable from root", d));
+        }
+        if let Some(d) = merge_visited.iter().position(|visited| !visited) {
+            return bad(format!("merge unit {} is not reachable from root", d));
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,11 @@
 use cts_plugin::Path;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::error::Error;
+
+mod check;
+mod wire;
+pub use wire::*;
 
 #[derive(Default)]
 pub struct ClockTree {
@@ -12,33 +18,54 @@ pub struct ClockTree {
     pub sinks: Vec<Sink>,
     pub buffers: Vec<Buffer>,
     pub merges: Vec<MergeUnit>,
+    pub source: Option<Location>, // clock source driving the root node
+    pub trunk: Vec<Path>,         // routed wire from source to root node, empty before routing
+    pub die_area: Option<(Location, Location)>, // (lower left, upper right)
+    pub obstacles: Vec<(Location, Location)>, // blockages and fixed macros, same as die_area
+    pub dummy_cap: f32,           // capacitance of a pseudo sink, input capacitance of dummy load
+    pub dummy_count: usize,       // pseudo sinks in sinks, numbering the next one
     // after gen_topology stage, length_map & fanout_map is generated
     pub length_map: HashMap<ClockTreeLevel, u32>, // <level, common length> mapping
     pub fanout_map: HashMap<ClockTreeLevel, u32>, // <level, fanout> mapping
 }
 
 impl ClockTree {
-    pub fn get_merge_unit_load_nodes(&self, idx: MergeUnitIndex) -> NodeSuccessor {
+    pub fn get_merge_unit_load_nodes(&self, idx: MergeUnitIndex) -> NodeSuccessor<'_> {
         NodeSuccessor {
             tree: self,
-            current_node_idx: self.merges[idx].first_node,
+            next_node_idx: Some(self.merges[idx].first_node),
         }
     }
-    pub fn get_buffer_load_nodes(&self, idx: BufferIndex) -> NodeSuccessor {
+    pub fn get_buffer_load_nodes(&self, idx: BufferIndex) -> NodeSuccessor<'_> {
         let merge_unit_load = self.buffers[idx].load;
         self.get_merge_unit_load_nodes(merge_unit_load)
     }
+    pub fn get_node_location(&self, idx: NodeIndex) -> Location {
+        match self.nodes[idx].node_owner {
+            NodeOwner::Buffer(d) => self.buffers[d].location,
+            NodeOwner::MergeUnit(d) => self.merges[d].location,
+            NodeOwner::Sink(d) => self.sinks[d].location,
+        }
+    }
+    // merge unit driven by the node, sink node has none
+    pub fn get_node_merge_unit(&self, idx: NodeIndex) -> Option<MergeUnitIndex> {
+        match self.nodes[idx].node_owner {
+            NodeOwner::Buffer(d) => Some(self.buffers[d].load),
+            NodeOwner::MergeUnit(d) => Some(d),
+            NodeOwner::Sink(_) => None,
+        }
+    }
     pub fn insert_buffer(
         &mut self,
         buffer_model: &str,
         buffer_idx: BufferIndex,
         node_idx: NodeIndex,
     ) -> Option<()> {
-        let mut node = &mut self.nodes[node_idx];
+        let node = &mut self.nodes[node_idx];
         match node.node_owner {
             NodeOwner::MergeUnit(d) => {
                 let new_buffer = Buffer {
-                    buffer_name: format!("{}_{}", buffer_model, node_idx as u32),
+                    buffer_name: format!("{}_{}_{}", self.name, buffer_model, node_idx as u32),
                     model_name: buffer_model.to_string(),
                     location: self.merges[d].location,
                     load: d, // load
@@ -66,7 +93,8 @@ pub struct MergeUnit {
     pub location: Location,    // root location
     pub common_length: u32,
     pub path: Vec<Path>,
-    pub if_horizontal: bool, // merge unit direction
+    pub if_horizontal: bool,    // merge unit direction
+    pub extra_length: Vec<u32>, // wirelength over common length of each child, for skew
 }
 
 impl MergeUnit {
@@ -74,6 +102,11 @@ impl MergeUnit {
         self.path.iter().fold(0, |acc, x| acc + x.length())
     }
 
+    // planned wirelength from the root to the i-th child
+    pub fn child_length(&self, i: usize) -> u32 {
+        self.common_length + self.extra_length.get(i).cloned().unwrap_or(0)
+    }
+
     // load sink , analyze range, get root and common length
     pub fn analyze_child_location(&mut self, sink: &[(i32, i32)]) {
         let (mut x_min, mut x_max, mut y_min, mut y_max) = (i32::MAX, i32::MIN, i32::MAX, i32::MIN);
@@ -134,19 +167,15 @@ pub struct Node {
 
 pub struct NodeSuccessor<'a> {
     tree: &'a ClockTree,
-    current_node_idx: NodeIndex,
+    next_node_idx: Option<NodeIndex>,
 }
 
 impl<'a> Iterator for NodeSuccessor<'a> {
     type Item = NodeIndex;
     fn next(&mut self) -> Option<NodeIndex> {
-        match self.tree.nodes[self.current_node_idx].next_node {
-            None => None,
-            Some(idx) => {
-                self.current_node_idx = idx;
-                Some(idx)
-            }
-        }
+        let idx = self.next_node_idx?;
+        self.next_node_idx = self.tree.nodes[idx].next_node;
+        Some(idx)
     }
 }
 
@@ -154,6 +183,10 @@ impl<'a> Iterator for NodeSuccessor<'a> {
 pub struct Sink {
     pub name: String, // cell name
     pub location: Location,
+    pub cap: f32,     // clock pin capacitance
+    pub latency: f32, // insertion delay of the generated clock tree driven by the sink
+    pub offset: f32,  // requested arrival offset for useful skew, 0 for zero skew
+    pub dummy: bool,  // pseudo sink padding the sink number, no cell unless dummy load is exported
 }
 
 pub struct Buffer {
@@ -163,19 +196,198 @@ pub struct Buffer {
     pub load: MergeUnitIndex, // load
 }
 
-// two dimensional look up table
+// timing model of a buffer cell, delay and transition take the worse of rise and fall
+pub struct BufferModel {
+    pub area: f32,
+    pub input_cap: f32,
+    pub max_cap: Option<f32>, // max capacitance of output pin
+    pub cell_rise: LutModel,
+    pub cell_fall: LutModel,
+    pub rise_transition: LutModel,
+    pub fall_transition: LutModel,
+}
+
+impl BufferModel {
+    pub fn get_delay(&self, input_slew: f32, load_cap: f32) -> f32 {
+        let rise = self.cell_rise.get_value(input_slew, load_cap);
+        let fall = self.cell_fall.get_value(input_slew, load_cap);
+        rise.max(fall)
+    }
+    pub fn get_transition(&self, input_slew: f32, load_cap: f32) -> f32 {
+        let rise = self.rise_transition.get_value(input_slew, load_cap);
+        let fall = self.fall_transition.get_value(input_slew, load_cap);
+        rise.max(fall)
+    }
+}
+
+// buffers available to buffering, gene 0 of buffer insertion solution means no buffer
+// and gene i (1..=N) means the i-th buffer of the list
+pub struct BufferLib {
+    buffers: Vec<(String, BufferModel)>,
+}
+
+impl BufferLib {
+    pub fn new(buffers: Vec<(String, BufferModel)>) -> Result<Self, Box<dyn Error>> {
+        if buffers.is_empty() {
+            return Err("buffer list is empty".into());
+        }
+        if buffers.len() >= u8::MAX as usize {
+            return Err(format!("too many buffers in buffer list: {}", buffers.len()).into());
+        }
+        Ok(BufferLib { buffers })
+    }
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+    // largest valid gene
+    pub fn max_gene(&self) -> u8 {
+        self.buffers.len() as u8
+    }
+    pub fn get_model(&self, name: &str) -> Option<&BufferModel> {
+        self.buffers.iter().find(|b| b.0 == name).map(|b| &b.1)
+    }
+    pub fn get(&self, gene: u8) -> Option<(&str, &BufferModel)> {
+        let (name, model) = self.buffers.get((gene as usize).checked_sub(1)?)?;
+        Some((name.as_str(), model))
+    }
+}
+
+// two dimensional look up table as NLDM table in liberty,
+// index_1 is input slew and index_2 is load capacitance
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LutModel {
     index_1: Vec<f32>,
     index_2: Vec<f32>,
-    value: Vec<Vec<f32>>,
+    value: Vec<Vec<f32>>, // value[i][j] at (index_1[i], index_2[j])
 }
 
 impl LutModel {
-    fn init_from_json(value:) -> Self {
+    pub fn new(
+        index_1: Vec<f32>,
+        index_2: Vec<f32>,
+        value: Vec<Vec<f32>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let lut = LutModel {
+            index_1,
+            index_2,
+            value,
+        };
+        lut.check()?;
+        Ok(lut)
+    }
 
+    // json object as {"index_1": [..], "index_2": [..], "value": [[..], ..]}
+    pub fn init_from_json(value: &serde_json::Value) -> Result<Self, Box<dyn Error>> {
+        let lut: LutModel = serde_json::from_value(value.clone())?;
+        lut.check()?;
+        Ok(lut)
     }
 
-    fn get_value(index_1:f32,index_2:f32) -> f32 {
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.index_1.is_empty() || self.index_2.is_empty() {
+            return Err("empty index in look up table".into());
+        }
+        let ascending = |index: &[f32]| index.windows(2).all(|d| d[0] < d[1]);
+        if !ascending(&self.index_1) || !ascending(&self.index_2) {
+            return Err("index of look up table must be strictly ascending".into());
+        }
+        if self.value.len() != self.index_1.len()
+            || self.value.iter().any(|row| row.len() != self.index_2.len())
+        {
+            return Err(format!(
+                "look up table value doesn't match index size {}x{}",
+                self.index_1.len(),
+                self.index_2.len()
+            )
+            .into());
+        }
+        Ok(())
+    }
 
+    // bilinear interpolation inside the table, linear extrapolation with the border
+    // interval outside the table
+    pub fn get_value(&self, index_1: f32, index_2: f32) -> f32 {
+        let (i, t1) = locate(&self.index_1, index_1);
+        let (j, t2) = locate(&self.index_2, index_2);
+        let v = |a: usize, b: usize| {
+            self.value[a.min(self.index_1.len() - 1)][b.min(self.index_2.len() - 1)]
+        };
+        let low = v(i, j) * (1.0 - t2) + v(i, j + 1) * t2;
+        let high = v(i + 1, j) * (1.0 - t2) + v(i + 1, j + 1) * t2;
+        low * (1.0 - t1) + high * t1
+    }
+}
+
+// find the grid interval for x, return its lower index and relative position in the interval.
+// The position is below 0 or above 1 when x is outside the index.
+fn locate(index: &[f32], x: f32) -> (usize, f32) {
+    if index.len() < 2 {
+        return (0, 0.0);
     }
-}
\ No newline at end of file
+    let i = index[1..index.len() - 1]
+        .iter()
+        .take_while(|d| x > **d)
+        .count();
+    (i, (x - index[i]) / (index[i + 1] - index[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_lut() -> LutModel {
+        LutModel::new(
+            vec![0.1, 0.3],
+            vec![0.01, 0.02, 0.04],
+            vec![vec![1.0, 2.0, 4.0], vec![3.0, 4.0, 6.0]],
+        )
+        .unwrap()
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn lut_interpolation() {
+        let lut = new_lut();
+        assert_close(lut.get_value(0.1, 0.02), 2.0);
+        assert_close(lut.get_value(0.3, 0.04), 6.0);
+        assert_close(lut.get_value(0.2, 0.02), 3.0);
+        assert_close(lut.get_value(0.2, 0.03), 4.0);
+    }
+
+    #[test]
+    fn lut_extrapolation() {
+        let lut = new_lut();
+        // border interval is extended on both sides of each index
+        assert_close(lut.get_value(0.5, 0.01), 5.0);
+        assert_close(lut.get_value(0.0, 0.01), 0.0);
+        assert_close(lut.get_value(0.1, 0.08), 8.0);
+        assert_close(lut.get_value(0.1, 0.0), 0.0);
+        // single point index gives the same value on that axis
+        let flat = LutModel::new(vec![0.1], vec![0.01, 0.02], vec![vec![1.0, 2.0]]).unwrap();
+        assert_close(flat.get_value(0.7, 0.015), 1.5);
+    }
+
+    #[test]
+    fn malformed_lut() {
+        assert!(LutModel::new(vec![], vec![0.1], vec![]).is_err());
+        assert!(LutModel::new(vec![0.2, 0.1], vec![0.1], vec![vec![1.0], vec![2.0]]).is_err());
+        assert!(LutModel::new(vec![0.1, 0.2], vec![0.1, 0.2], vec![vec![1.0, 2.0]]).is_err());
+        let json = serde_json::json!({
+            "index_1": [0.1, 0.2],
+            "index_2": [0.1],
+            "value": [[1.0], [2.0, 3.0]]
+        });
+        assert!(LutModel::init_from_json(&json).is_err());
+        let json = serde_json::json!({"index_1": [0.1], "index_2": [0.1], "value": [[1.0]]});
+        assert_close(
+            LutModel::init_from_json(&json).unwrap().get_value(0.1, 0.1),
+            1.0,
+        );
+    }
+}
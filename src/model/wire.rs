@@ -0,0 +1,304 @@
+// wire parasitic model of clock routing, loaded from rho matrix file
+use super::*;
+
+// resistance and capacitance per unit length(DBU) of one routing layer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct LayerRc {
+    pub unit_res: f32,
+    pub unit_cap: f32,
+}
+
+// rho matrix file in yaml, rc of every routing layer:
+// layers:
+//   metal4: { unit_res: 0.0001, unit_cap: 0.0000002 }
+//   metal5: { unit_res: 0.0001, unit_cap: 0.0000002 }
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RhoMatrix {
+    pub layers: HashMap<String, LayerRc>,
+}
+
+// rc of clock wire, horizontal and vertical segments are routed on different layers
+#[derive(Debug, Clone, Copy)]
+pub struct WireModel {
+    pub horizontal: LayerRc,
+    pub vertical: LayerRc,
+}
+
+// wire delay model of timing analysis
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DelayMetric {
+    Elmore, // first moment, an upper bound of the delay
+    D2M,    // delay from the first two moments, closer to the delay of long wires
+}
+
+impl WireModel {
+    pub fn new<P: AsRef<std::path::Path>>(
+        rho_matrix_path: P,
+        horizontal_layer: &str,
+        vertical_layer: &str,
+    ) -> Result<WireModel, Box<dyn Error>> {
+        let rho_matrix: RhoMatrix =
+            serde_yaml::from_str(&std::fs::read_to_string(rho_matrix_path)?)?;
+        let get_layer = |layer: &str| -> Result<LayerRc, Box<dyn Error>> {
+            rho_matrix
+                .layers
+                .get(layer)
+                .cloned()
+                .ok_or_else(|| format!("layer {} is not found in rho matrix", layer).into())
+        };
+        Ok(WireModel {
+            horizontal: get_layer(horizontal_layer)?,
+            vertical: get_layer(vertical_layer)?,
+        })
+    }
+
+    // (resistance, capacitance) of a straight segment
+    pub fn get_segment_rc(&self, from: Location, to: Location) -> (f32, f32) {
+        let dx = (from.0 - to.0).abs() as f32;
+        let dy = (from.1 - to.1).abs() as f32;
+        (
+            self.horizontal.unit_res * dx + self.vertical.unit_res * dy,
+            self.horizontal.unit_cap * dx + self.vertical.unit_cap * dy,
+        )
+    }
+
+    // (resistance, capacitance) of unrouted wire, assumed half horizontal and half vertical
+    pub fn get_length_rc(&self, length: f32) -> (f32, f32) {
+        (
+            (self.horizontal.unit_res + self.vertical.unit_res) / 2.0 * length,
+            (self.horizontal.unit_cap + self.vertical.unit_cap) / 2.0 * length,
+        )
+    }
+}
+
+impl ClockTree {
+    // Wire from merge unit root to each child as straight segments in root-to-child order.
+    // Routed path of each child is stored from child to root, so a route ends at the first
+    // path reaching the root. Unrouted merge unit takes L-shape estimation.
+    pub fn get_merge_unit_routes(&self, midx: MergeUnitIndex) -> Vec<Vec<(Location, Location)>> {
+        let root = self.merges[midx].location;
+        let childs: Vec<NodeIndex> = self.get_merge_unit_load_nodes(midx).collect();
+        let mut routes = vec![];
+        if self.merges[midx].path.is_empty() {
+            for child in childs {
+                let from = self.get_node_location(child);
+                let turn = (from.0, root.1);
+                routes.push(vec![(root, turn), (turn, from)]);
+            }
+            return routes;
+        }
+        let mut points = vec![];
+        for path in &self.merges[midx].path {
+            if points.is_empty() {
+                points.push(path.from);
+            }
+            if let Some(turn) = path.turn {
+                points.push(turn);
+            }
+            points.push(path.to);
+            if path.to == root {
+                routes.push(points.windows(2).rev().map(|d| (d[1], d[0])).collect());
+                points.clear();
+            }
+        }
+        routes
+    }
+
+    // Wire from clock source to root node as straight segments in source-to-root order,
+    // the routed trunk or L-shape estimation before routing. It's empty without source.
+    pub fn get_trunk_route(&self) -> Vec<(Location, Location)> {
+        let source = match self.source {
+            Some(source) => source,
+            None => return vec![],
+        };
+        if self.trunk.is_empty() {
+            let root = self.get_node_location(self.root_node_index);
+            let turn = (source.0, root.1);
+            return vec![(source, turn), (turn, root)];
+        }
+        let mut points = vec![];
+        for path in &self.trunk {
+            if points.is_empty() {
+                points.push(path.from);
+            }
+            points.extend(path.turn);
+            points.push(path.to);
+        }
+        points.windows(2).map(|d| (d[0], d[1])).collect()
+    }
+
+    pub fn get_merge_unit_wire_cap(&self, midx: MergeUnitIndex, wire: &WireModel) -> f32 {
+        self.get_merge_unit_routes(midx)
+            .iter()
+            .flatten()
+            .map(|(from, to)| wire.get_segment_rc(*from, *to).1)
+            .sum()
+    }
+
+    // load seen at merge unit root, `child_caps` is input capacitance of each child in order
+    pub fn get_merge_unit_load(
+        &self,
+        midx: MergeUnitIndex,
+        wire: &WireModel,
+        child_caps: &[f32],
+    ) -> f32 {
+        self.get_merge_unit_wire_cap(midx, wire) + child_caps.iter().sum::<f32>()
+    }
+
+    // wire delay from merge unit root to each child in order, `child_caps` is input
+    // capacitance of each child. Child routes only share the root, so each route is
+    // solved as a RC chain with pi model segments.
+    pub fn get_merge_unit_wire_delay(
+        &self,
+        midx: MergeUnitIndex,
+        wire: &WireModel,
+        child_caps: &[f32],
+        metric: DelayMetric,
+    ) -> Vec<f32> {
+        self.get_merge_unit_routes(midx)
+            .iter()
+            .zip(child_caps)
+            .map(|(route, load)| {
+                let rc: Vec<(f32, f32)> = route
+                    .iter()
+                    .map(|(from, to)| wire.get_segment_rc(*from, *to))
+                    .collect();
+                chain_delay(&rc, *load, metric)
+            })
+            .collect()
+    }
+}
+
+// delay at the end of RC chain driven from its start, `rc` is (resistance, capacitance) of
+// each segment. D2M takes the first two moments: ln2 * m1^2 / sqrt(m2).
+pub fn chain_delay(rc: &[(f32, f32)], load_cap: f32, metric: DelayMetric) -> f32 {
+    // lumped capacitance at the far end of each segment
+    let caps: Vec<f32> = (0..rc.len())
+        .map(|i| rc[i].1 / 2.0 + rc.get(i + 1).map_or(load_cap, |d| d.1 / 2.0))
+        .collect();
+    let moment = |weights: &[f32]| -> Vec<f32> {
+        let mut moments = vec![0.0; rc.len()];
+        let mut acc = 0.0;
+        for i in 0..rc.len() {
+            let downstream: f32 = (i..rc.len()).map(|j| caps[j] * weights[j]).sum();
+            acc += rc[i].0 * downstream;
+            moments[i] = acc;
+        }
+        moments
+    };
+    let m1 = moment(&vec![1.0; rc.len()]);
+    let elmore = m1.last().cloned().unwrap_or(0.0);
+    match metric {
+        DelayMetric::Elmore => elmore,
+        DelayMetric::D2M => {
+            let m2 = moment(&m1).last().cloned().unwrap_or(0.0);
+            if m2 > 0.0 {
+                2.0_f32.ln() * elmore * elmore / m2.sqrt()
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+// wirelength whose Elmore delay with `load` at its end is `delay`, the positive root of
+// r*c/2*l^2 + r*load*l = delay, `r` and `c` are per unit length
+pub fn solve_elmore_length(r: f64, c: f64, load: f64, delay: f64) -> f64 {
+    if delay <= 0.0 || r <= 0.0 {
+        return 0.0;
+    }
+    let a = r * c / 2.0;
+    let b = r * load;
+    if a <= f64::EPSILON * b {
+        return if b > 0.0 { delay / b } else { 0.0 };
+    }
+    (-b + (b * b + 4.0 * a * delay).sqrt()) / (2.0 * a)
+}
+
+// PERI slew degradation through wire with Elmore delay
+pub fn degrade_slew(slew: f32, elmore: f32) -> f32 {
+    let wire_slew = 9.0_f32.ln() * elmore;
+    (slew * slew + wire_slew * wire_slew).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    fn new_wire() -> WireModel {
+        WireModel {
+            horizontal: LayerRc {
+                unit_res: 2.0,
+                unit_cap: 3.0,
+            },
+            vertical: LayerRc {
+                unit_res: 5.0,
+                unit_cap: 7.0,
+            },
+        }
+    }
+
+    #[test]
+    fn rho_matrix_layers() {
+        let path = std::env::temp_dir().join(format!("rho_matrix_{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            "layers:\n  metal2: { unit_res: 5.0, unit_cap: 7.0 }\n  metal3: { unit_res: 2.0, unit_cap: 3.0 }\n",
+        )
+        .unwrap();
+        let wire = WireModel::new(&path, "metal3", "metal2").unwrap();
+        assert_close(wire.horizontal.unit_res, 2.0);
+        assert_close(wire.vertical.unit_cap, 7.0);
+        assert!(WireModel::new(&path, "metal3", "metal4").is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(WireModel::new(&path, "metal3", "metal2").is_err());
+    }
+
+    #[test]
+    fn segment_and_length_rc() {
+        let wire = new_wire();
+        let (r, c) = wire.get_segment_rc((10, 4), (0, 0));
+        assert_close(r, 2.0 * 10.0 + 5.0 * 4.0);
+        assert_close(c, 3.0 * 10.0 + 7.0 * 4.0);
+        let (r, c) = wire.get_length_rc(10.0);
+        assert_close(r, 35.0);
+        assert_close(c, 50.0);
+    }
+
+    #[test]
+    fn chain_delay_metrics() {
+        // node capacitance is 1 + 2 after the first segment and 2 + 5 at the end
+        let rc = [(1.0, 2.0), (3.0, 4.0)];
+        let elmore = chain_delay(&rc, 5.0, DelayMetric::Elmore);
+        assert_close(elmore, 1.0 * (3.0 + 7.0) + 3.0 * 7.0);
+        // m2 = 1 * (3 * 10 + 7 * 31) + 3 * (7 * 31)
+        let d2m = chain_delay(&rc, 5.0, DelayMetric::D2M);
+        assert_close(d2m, 2.0_f32.ln() * 31.0 * 31.0 / 898.0_f32.sqrt());
+        assert!(d2m <= elmore);
+        assert_close(chain_delay(&[], 5.0, DelayMetric::D2M), 0.0);
+    }
+
+    #[test]
+    fn elmore_length() {
+        // 2 * 4 / 2 * 3^2 + 2 * 1 * 3 = 42
+        assert!((solve_elmore_length(2.0, 4.0, 1.0, 42.0) - 3.0).abs() < 1e-9);
+        assert!((solve_elmore_length(2.0, 0.0, 1.0, 42.0) - 21.0).abs() < 1e-9);
+        assert_eq!(solve_elmore_length(2.0, 4.0, 1.0, 0.0), 0.0);
+        // the length gives back the delay on the wire model
+        let wire = new_wire();
+        let (r, c) = wire.get_length_rc(1.0);
+        let length = solve_elmore_length(r as f64, c as f64, 0.5, 1000.0);
+        let rc = wire.get_length_rc(length as f32);
+        assert!((chain_delay(&[rc], 0.5, DelayMetric::Elmore) - 1000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn slew_degradation() {
+        assert_close(degrade_slew(3.0, 4.0 / 9.0_f32.ln()), 5.0);
+        assert_close(degrade_slew(3.0, 0.0), 3.0);
+    }
+}
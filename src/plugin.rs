@@ -0,0 +1,62 @@
+use crate::model::Location;
+use cts_plugin::CTSPlugin;
+use std::error::Error;
+
+/// Design database operations needed by CTS beyond `CTSPlugin`
+///
+/// The plugin library exports `new_cts_export_plugin` returning `Box<dyn DesignPlugin>`,
+/// besides `new_design_plugin` returning the plain `Box<dyn CTSPlugin>`. The design is read
+/// through it and the synthesized clock trees are written back through it, so DEF is only
+/// read and written by the plugin.
+pub trait DesignPlugin: CTSPlugin {
+    /// (instance, pin) pairs connected to `net`, top level pins have "PIN" as instance
+    fn get_net_pins(&self, net: &str) -> Result<Vec<(String, String)>, Box<dyn Error>>;
+
+    /// Net connected to `pin` of instance `inst`, None when the pin is unconnected
+    fn get_pin_net(&self, inst: &str, pin: &str) -> Result<Option<String>, Box<dyn Error>>;
+
+    /// Cell of instance `inst`, None when there's no such instance
+    fn get_cell_master(&self, inst: &str) -> Result<Option<String>, Box<dyn Error>>;
+
+    /// Location of the top level pin on `net`, None when the net has no placed one
+    fn get_pin_location(&self, net: &str) -> Result<Option<Location>, Box<dyn Error>>;
+
+    /// Die area as (lower left, upper right), None when the design doesn't set it
+    fn get_die_area(&self) -> Result<Option<(Location, Location)>, Box<dyn Error>>;
+
+    /// Placement and routing blockages, as (lower left, upper right) rectangles
+    fn get_blockages(&self) -> Result<Vec<(Location, Location)>, Box<dyn Error>>;
+
+    /// Bounding boxes of the placed fixed macros, as (lower left, upper right) rectangles
+    fn get_fixed_macros(&self) -> Result<Vec<(Location, Location)>, Box<dyn Error>>;
+
+    /// Place a new component of `cell`
+    fn add_component(
+        &mut self,
+        name: &str,
+        cell: &str,
+        location: Location,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Create a clock net, or replace the connections and routing of an existing one
+    fn put_net(&mut self, net: &RoutedNet) -> Result<(), Box<dyn Error>>;
+
+    /// Write the design with all changes into DEF at `path`
+    fn export_def(&self, path: &str) -> Result<(), Box<dyn Error>>;
+}
+
+// a net with its connections and the straight wire segments routing it
+#[derive(Debug, PartialEq)]
+pub struct RoutedNet {
+    pub name: String,
+    pub pins: Vec<(String, String)>, // (instance, pin), "PIN" for top level pin
+    pub wires: Vec<WireSegment>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WireSegment {
+    pub layer: String,
+    pub from: Location,
+    pub to: Location,
+    pub via: Option<String>, // via at `to` into the layer of the next segment
+}
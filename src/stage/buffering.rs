@@ -1,72 +1,269 @@
 use crate::model::*;
 
 use super::cfg::BufferingCfg;
-use mincost::{Evolution, EvolutionConfig, Individual};
-use std::collections::HashMap;
+use crate::error::CtsError;
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::cmp::Ordering;
 
-impl ClockTree {
-    pub fn buffering(&mut self, cfg: &BufferingCfg) -> Option<()> {
-        let buffer_lib: HashMap<u8, String> = HashMap::new();
+// cost added per unit of slew exceeding max_slew
+const SLEW_PENALTY: f32 = 1000.0;
 
-        let upper = cfg.buffer_list.len() as u8;
-        let evolution_cfg = EvolutionConfig {
-            pop_size: cfg.pop_size,
-            elite_size: cfg.elite_size,
-            mutation_rate: cfg.mutation_rate,
-            generations: cfg.generations,
-            individual_length: self.tree_level,
-            upper: Some(upper),
-            lower: Some(0),
-        };
+// timing of one buffer insertion solution, latency is arrival minus requested offset
+struct InsertionTiming {
+    max_latency: f32,
+    min_latency: f32,
+    offset_error: f32,   // mean distance of real sinks from their mean latency
+    slew_violation: f32, // sum of slew exceeding max_slew
+}
 
-        // fitness function in buffering
+impl InsertionTiming {
+    // Skew is the worst offset error, and the mean offset error counts how much wire the
+    // skew planning has to add to meet the requested offsets of all sinks.
+    fn cost(&self) -> f32 {
+        let skew = self.max_latency - self.min_latency;
+        self.max_latency + skew + self.offset_error + SLEW_PENALTY * self.slew_violation
+    }
+}
 
-        // Individal<u8> means each level's insertion result in clock tree, in top-down order
-        // Say, if there is totally 3 level in clock tree, then Individal<u8> length = 3
-        let fitness = |solution: &Individual<u8>| -> f32 { 0.0 };
+impl ClockTree {
+    pub fn buffering(
+        &mut self,
+        cfg: &BufferingCfg,
+        buffer_lib: &BufferLib,
+        wire: &WireModel,
+        rng: &mut StdRng,
+    ) -> Result<(), CtsError> {
+        cfg.check()?;
+        // gene range is [0, upper), 0 is kept for no buffer
+        let upper = buffer_lib.max_gene() + 1;
 
-        let mut evolution = Evolution::init_with_range(evolution_cfg, fitness).unwrap();
-        let final_solution: Individual<u8> = evolution.evolute().unwrap();
-        // TODO: check buffer insertion solution equal to clock tree level
-        assert!(true);
+        // fitness function in buffering
 
-        let mut current_nodes = vec![];
-        let mut next_nodes = vec![];
-        let mut buffer_inserted_num: BufferIndex = 0;
-        for (level, insertion) in final_solution.genes.iter().enumerate() {
-            next_nodes.clear();
-            if level == 0 {
-                let root_merge: MergeUnitIndex = match self.nodes[self.root_node_index].node_owner {
-                    NodeOwner::MergeUnit(midx) => midx,
-                    _ => return None,
-                };
-                if *insertion != 0 {
-                    let buffer_model: &str = buffer_lib.get(&insertion).unwrap();
-                    self.insert_buffer(buffer_model, buffer_inserted_num, self.root_node_index);
-                    buffer_inserted_num += 1;
+        // Individal means each level's insertion result in clock tree, in top-down order
+        // Say, if there is totally 3 level in clock tree, then Individal length = 3
+        // Gene 0 means no buffer in that level. Unknown buffer gives the worst cost.
+        let final_solution: Individual = {
+            let fitness = |genes: &[u8]| -> f32 {
+                let mut insertion: Vec<Option<&BufferModel>> = vec![];
+                for gene in genes {
+                    if *gene == 0 {
+                        insertion.push(None);
+                    } else {
+                        match buffer_lib.get(*gene) {
+                            Some((_, model)) => insertion.push(Some(model)),
+                            None => return f32::MAX,
+                        }
+                    }
+                }
+                match self.evaluate_insertion(&insertion, wire, cfg) {
+                    Some(timing) => timing.cost(),
+                    None => f32::MAX,
                 }
-                next_nodes = self
-                    .get_merge_unit_load_nodes(root_merge)
-                    .into_iter()
-                    .collect();
-            } else {
-                if *insertion != 0 {
-                    let buffer_model: &str = buffer_lib.get(&insertion).unwrap();
-                    for nidx in &current_nodes {
-                        self.insert_buffer(buffer_model, buffer_inserted_num, *nidx);
-                        buffer_inserted_num += 1;
+            };
+            evolute(cfg, self.tree_level, upper, rng, fitness)?
+        };
+        if final_solution.genes.len() != self.tree_level {
+            return Err(CtsError::Ga(format!(
+                "solution has {} genes for {} levels",
+                final_solution.genes.len(),
+                self.tree_level
+            )));
+        }
+        if let Some(gene) = final_solution.genes.iter().find(|g| **g >= upper) {
+            return Err(CtsError::Ga(format!(
+                "gene {} is out of buffer library",
+                gene
+            )));
+        }
+
+        // insert buffer at every merge node of the level, level 0 is the root
+        let mut current_nodes = vec![self.root_node_index];
+        for gene in final_solution.genes.iter() {
+            if let Some((buffer_model, _)) = buffer_lib.get(*gene) {
+                for nidx in &current_nodes {
+                    if let NodeOwner::MergeUnit(_) = self.nodes[*nidx].node_owner {
+                        self.insert_buffer(buffer_model, self.buffers.len(), *nidx)
+                            .ok_or_else(|| {
+                                CtsError::BadTopology(format!(
+                                    "can't insert buffer at node {}",
+                                    nidx
+                                ))
+                            })?;
+                    }
+                }
+            }
+            current_nodes = current_nodes
+                .iter()
+                .filter_map(|nidx| self.get_node_merge_unit(*nidx))
+                .flat_map(|midx| self.get_merge_unit_load_nodes(midx))
+                .collect();
+        }
+        Ok(())
+    }
+    // Evaluate latency, skew and slew of inserting `insertion[level]` at every merge node of
+    // each level. Wire between a merge unit and its children takes their planned length,
+    // wire delay is Elmore delay and slew degradation follows PERI model.
+    fn evaluate_insertion(
+        &self,
+        insertion: &[Option<&BufferModel>],
+        wire: &WireModel,
+        cfg: &BufferingCfg,
+    ) -> Option<InsertionTiming> {
+        // capacitance seen at each node input, and seen at each merge unit root
+        let mut node_cap = vec![0.0; self.nodes.len()];
+        let mut merge_load = vec![0.0; self.nodes.len()];
+        self.fill_load_cap(
+            self.root_node_index,
+            0,
+            insertion,
+            wire,
+            &mut node_cap,
+            &mut merge_load,
+        )?;
+
+        let mut timing = InsertionTiming {
+            max_latency: f32::MIN,
+            min_latency: f32::MAX,
+            offset_error: 0.0,
+            slew_violation: 0.0,
+        };
+        let mut real_latency = vec![];
+        // (node, level, arrival time, slew at driver output, wire delay from driver)
+        let mut stack = vec![(self.root_node_index, 0, 0.0, cfg.input_slew, 0.0)];
+        while let Some((nidx, level, mut arrival, mut driver_slew, mut wire_delay)) = stack.pop() {
+            let slew = degrade_slew(driver_slew, wire_delay);
+            if slew > cfg.max_slew {
+                timing.slew_violation += slew - cfg.max_slew;
+            }
+            let midx = match self.nodes[nidx].node_owner {
+                NodeOwner::Sink(d) => {
+                    // offset requested by the sink is not counted as skew
+                    let latency = arrival + self.sinks[d].latency - self.sinks[d].offset;
+                    timing.max_latency = timing.max_latency.max(latency);
+                    timing.min_latency = timing.min_latency.min(latency);
+                    if !self.sinks[d].dummy {
+                        real_latency.push(latency);
                     }
+                    continue;
+                }
+                _ => self.get_node_merge_unit(nidx)?,
+            };
+            if let Some(model) = insertion.get(level).cloned().flatten() {
+                arrival += model.get_delay(slew, merge_load[nidx]);
+                driver_slew = model.get_transition(slew, merge_load[nidx]);
+                wire_delay = 0.0;
+            }
+            for (i, child) in self.get_merge_unit_load_nodes(midx).enumerate() {
+                let length = self.merges[midx].child_length(i) as f32;
+                let (res, cap) = wire.get_length_rc(length);
+                let elmore = res * (cap / 2.0 + node_cap[child]);
+                stack.push((
+                    child,
+                    level + 1,
+                    arrival + elmore,
+                    driver_slew,
+                    wire_delay + elmore,
+                ));
+            }
+        }
+        if !real_latency.is_empty() {
+            let n = real_latency.len() as f32;
+            let mean = real_latency.iter().sum::<f32>() / n;
+            timing.offset_error = real_latency.iter().map(|d| (d - mean).abs()).sum::<f32>() / n;
+        }
+        Some(timing)
+    }
+
+    fn fill_load_cap(
+        &self,
+        nidx: NodeIndex,
+        level: ClockTreeLevel,
+        insertion: &[Option<&BufferModel>],
+        wire: &WireModel,
+        node_cap: &mut Vec<f32>,
+        merge_load: &mut Vec<f32>,
+    ) -> Option<f32> {
+        let midx = match self.nodes[nidx].node_owner {
+            NodeOwner::Sink(d) => {
+                node_cap[nidx] = self.sinks[d].cap;
+                return Some(node_cap[nidx]);
+            }
+            _ => self.get_node_merge_unit(nidx)?,
+        };
+        let childs: Vec<NodeIndex> = self.get_merge_unit_load_nodes(midx).collect();
+        let mut load = 0.0;
+        for (i, child) in childs.into_iter().enumerate() {
+            let length = self.merges[midx].child_length(i) as f32;
+            load += wire.get_length_rc(length).1
+                + self.fill_load_cap(child, level + 1, insertion, wire, node_cap, merge_load)?;
+        }
+        merge_load[nidx] = load;
+        node_cap[nidx] = match insertion.get(level).cloned().flatten() {
+            Some(model) => model.input_cap,
+            None => load,
+        };
+        Some(node_cap[nidx])
+    }
+}
+
+#[derive(Clone)]
+struct Individual {
+    genes: Vec<u8>,
+    cost: f32,
+}
+
+// Genetic search for the solution of least cost. Elites are kept in each generation and
+// the others are bred by tournament selection, one-point crossover and random mutation.
+// Every random decision is drawn from `rng`, so a seeded `rng` gives the same solution.
+fn evolute<F: Fn(&[u8]) -> f32>(
+    cfg: &BufferingCfg,
+    length: usize,
+    upper: u8,
+    rng: &mut StdRng,
+    fitness: F,
+) -> Result<Individual, CtsError> {
+    let by_cost =
+        |a: &Individual, b: &Individual| a.cost.partial_cmp(&b.cost).unwrap_or(Ordering::Equal);
+    let new_individual = |genes: Vec<u8>| Individual {
+        cost: fitness(&genes),
+        genes,
+    };
+    let mut population: Vec<Individual> = (0..cfg.pop_size)
+        .map(|_| new_individual((0..length).map(|_| rng.gen_range(0..upper)).collect()))
+        .collect();
+    population.sort_by(by_cost);
+    for _ in 0..cfg.generations {
+        let mut next: Vec<Individual> = population[..cfg.elite_size].to_vec();
+        while next.len() < cfg.pop_size {
+            let select = |rng: &mut StdRng| {
+                let a = &population[rng.gen_range(0..population.len())];
+                let b = &population[rng.gen_range(0..population.len())];
+                if by_cost(a, b) == Ordering::Greater {
+                    b
+                } else {
+                    a
                 }
-                for nidx in current_nodes {
-                    let root_merge: MergeUnitIndex = match self.nodes[nidx].node_owner {
-                        NodeOwner::MergeUnit(midx) => midx,
-                        _ => return None,
-                    };
-                    next_nodes.extend(self.get_merge_unit_load_nodes(root_merge).into_iter());
+            };
+            let (father, mother) = (select(rng), select(rng));
+            let cut = rng.gen_range(0..=length);
+            let mut genes: Vec<u8> = father.genes[..cut]
+                .iter()
+                .chain(&mother.genes[cut..])
+                .cloned()
+                .collect();
+            for gene in genes.iter_mut() {
+                if rng.gen::<f32>() < cfg.mutation_rate {
+                    *gene = rng.gen_range(0..upper);
                 }
             }
-            current_nodes = next_nodes.clone();
+            next.push(new_individual(genes));
         }
-        Some(())
+        next.sort_by(by_cost);
+        population = next;
     }
+    population
+        .into_iter()
+        .next()
+        .ok_or_else(|| CtsError::Ga("empty population".to_string()))
 }
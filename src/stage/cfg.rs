@@ -1,24 +1,274 @@
+use crate::error::CtsError;
+use crate::model::DelayMetric;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize)]
 pub struct GenTopologyCfg {
     pub max_branch: usize,
+    pub min_branch: Option<usize>,          // 2 when it's not set
+    pub grouping: Option<GroupingStrategy>, // Angular when it's not set
+    pub balance: Option<GroupBalance>,      // Capacitance when it's not set
+    pub merging: Option<MergeEmbedding>,    // Midpoint when it's not set
+}
+
+impl GenTopologyCfg {
+    pub fn check(&self) -> Result<(), CtsError> {
+        let min_branch = self.min_branch.unwrap_or(2);
+        if min_branch < 2 || self.max_branch < min_branch {
+            return Err(CtsError::BadTopology(format!(
+                "branch number must satisfy 2 <= min_branch <= max_branch, got {}..={}",
+                min_branch, self.max_branch
+            )));
+        }
+        Ok(())
+    }
+}
+
+// how sinks are split into groups of each merge level
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum GroupingStrategy {
+    Angular,     // sectors by polar angle around the center of all sinks
+    Bipartition, // balanced recursive geometric cuts at x/y median
+}
+
+// what groups of a level are balanced on
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum GroupBalance {
+    Count,       // number of sinks
+    Capacitance, // total clock pin capacitance of sinks
+}
+
+// where merge units are placed and how long the wire to each child is
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum MergeEmbedding {
+    Midpoint,  // midpoint of the extreme childs, same wirelength in a level
+    Dme,       // deferred-merge embedding balancing path length
+    ElmoreDme, // deferred-merge embedding balancing Elmore delay
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct BufferingCfg {
     pub buffer_list: Vec<String>,
+    pub liberty_paths: Vec<String>, // liberty files of buffers and sinks
     pub input_slew: f32,
     pub max_slew: f32,
-    pub rho_matrix_path: String,
+    pub rho_matrix_path: String, // rc of routing layers, see model::RhoMatrix
+    pub delay_metric: Option<DelayMetric>, // wire delay of timing analysis, Elmore when it's not set
     pub pop_size: usize,
     pub elite_size: usize,
     pub mutation_rate: f32,
     pub generations: usize,
 }
 
+impl BufferingCfg {
+    // GA parameters
+    pub fn check(&self) -> Result<(), CtsError> {
+        if self.pop_size == 0 || self.elite_size > self.pop_size {
+            return Err(CtsError::Ga(format!(
+                "pop_size {} must be positive and no less than elite_size {}",
+                self.pop_size, self.elite_size
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.mutation_rate) {
+            return Err(CtsError::Ga(format!(
+                "mutation_rate {} must be in [0, 1]",
+                self.mutation_rate
+            )));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize)]
-pub struct CtsCfg {
+pub struct ExportCfg {
+    pub def_path: String,     // output DEF path
+    pub verilog_path: String, // output netlist path
+    pub buffer_input_pin: String,
+    pub buffer_output_pin: String,
+    pub horizontal_layer: String,
+    pub vertical_layer: String,
+    pub via_name: Option<String>, // via dropped where routing changes layer
+    pub dummy_load: Option<DummyLoadCfg>, // pseudo sinks are exported only when it's set
+}
+
+// cell placed at each pseudo sink as dummy load
+#[derive(Serialize, Deserialize)]
+pub struct DummyLoadCfg {
+    pub cell: String,
+    pub input_pin: String,
+}
+
+// synthesis setting of one clock
+#[derive(Serialize, Deserialize)]
+pub struct ClockCfg {
     pub name: String, // clock net name
     pub stage1_cfg: GenTopologyCfg,
     pub stage2_cfg: BufferingCfg,
+    pub latency_offsets: Option<Vec<LatencyOffsetCfg>>, // zero skew when it's not set
+}
+
+// Requested arrival offset of the sinks whose name fully matches regex `sinks`, in time
+// unit of liberty. A later entry overrides earlier ones for the same sink.
+#[derive(Serialize, Deserialize)]
+pub struct LatencyOffsetCfg {
+    pub sinks: String,
+    pub offset: f32,
+}
+
+impl ClockCfg {
+    // compile regex of latency offsets
+    pub fn get_latency_offsets(&self) -> Result<LatencyOffsets, CtsError> {
+        let mut offsets = vec![];
+        for d in self.latency_offsets.iter().flatten() {
+            let pattern =
+                Regex::new(&format!("^(?:{})$", d.sinks)).map_err(|e| CtsError::ConfigParse {
+                    path: format!("latency offsets of clock {}", self.name),
+                    reason: e.to_string(),
+                })?;
+            offsets.push((pattern, d.offset));
+        }
+        Ok(LatencyOffsets(offsets))
+    }
+}
+
+pub struct LatencyOffsets(Vec<(Regex, f32)>);
+
+impl LatencyOffsets {
+    // offset of the last matched entry, 0 when none is matched
+    pub fn get(&self, sink: &str) -> f32 {
+        self.0
+            .iter()
+            .rev()
+            .find(|d| d.0.is_match(sink))
+            .map_or(0.0, |d| d.1)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(from = "CtsCfgSchema")]
+pub struct CtsCfg {
+    pub seed: Option<u64>, // seed of every random decision, drawn at random when it's not set
+    pub clocks: Vec<ClockCfg>,
+    pub export_cfg: ExportCfg, // all clock trees are exported into one DEF and netlist
+}
+
+// CTS config with a list of clocks, or the single clock schema with the clock setting at
+// the top level
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CtsCfgSchema {
+    Clocks {
+        seed: Option<u64>,
+        clocks: Vec<ClockCfg>,
+        export_cfg: ExportCfg,
+    },
+    Single {
+        seed: Option<u64>,
+        name: String,
+        stage1_cfg: GenTopologyCfg,
+        stage2_cfg: BufferingCfg,
+        latency_offsets: Option<Vec<LatencyOffsetCfg>>,
+        export_cfg: ExportCfg,
+    },
+}
+
+impl From<CtsCfgSchema> for CtsCfg {
+    fn from(schema: CtsCfgSchema) -> Self {
+        match schema {
+            CtsCfgSchema::Clocks {
+                seed,
+                clocks,
+                export_cfg,
+            } => CtsCfg {
+                seed,
+                clocks,
+                export_cfg,
+            },
+            CtsCfgSchema::Single {
+                seed,
+                name,
+                stage1_cfg,
+                stage2_cfg,
+                latency_offsets,
+                export_cfg,
+            } => CtsCfg {
+                seed,
+                clocks: vec![ClockCfg {
+                    name,
+                    stage1_cfg,
+                    stage2_cfg,
+                    latency_offsets,
+                }],
+                export_cfg,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT_CFG: &str = "
+export_cfg:
+  def_path: out.def
+  verilog_path: out.v
+  buffer_input_pin: A
+  buffer_output_pin: Z
+  horizontal_layer: metal3
+  vertical_layer: metal2
+";
+
+    const CLOCK_CFG: &str = "
+stage1_cfg:
+  max_branch: 4
+stage2_cfg:
+  buffer_list: [BUF_X1]
+  liberty_paths: [test.lib]
+  input_slew: 0.05
+  max_slew: 0.2
+  rho_matrix_path: rho.json
+  pop_size: 20
+  elite_size: 2
+  mutation_rate: 0.1
+  generations: 10
+";
+
+    // clock setting as an item of `clocks`
+    fn clock_item(name: &str) -> String {
+        let body: String = CLOCK_CFG.lines().map(|l| format!("    {}\n", l)).collect();
+        format!("  - name: {}\n{}", name, body)
+    }
+
+    #[test]
+    fn single_clock_schema() {
+        let single = format!("seed: 3\nname: clk\n{}{}", CLOCK_CFG, EXPORT_CFG);
+        let cts_cfg: CtsCfg = serde_yaml::from_str(&single).unwrap();
+        assert_eq!(cts_cfg.seed, Some(3));
+        assert_eq!(cts_cfg.clocks.len(), 1);
+        assert_eq!(cts_cfg.clocks[0].name, "clk");
+        assert_eq!(cts_cfg.clocks[0].stage1_cfg.max_branch, 4);
+
+        let clocks = format!(
+            "clocks:\n{}{}{}",
+            clock_item("clk_a"),
+            clock_item("clk_b"),
+            EXPORT_CFG
+        );
+        let cts_cfg: CtsCfg = serde_yaml::from_str(&clocks).unwrap();
+        assert_eq!(cts_cfg.seed, None);
+        let names: Vec<&str> = cts_cfg.clocks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["clk_a", "clk_b"]);
+        assert_eq!(cts_cfg.clocks[0].stage2_cfg.delay_metric, None);
+
+        let d2m = format!(
+            "name: clk\n{}  delay_metric: D2M\n{}",
+            CLOCK_CFG, EXPORT_CFG
+        );
+        let cts_cfg: CtsCfg = serde_yaml::from_str(&d2m).unwrap();
+        assert_eq!(
+            cts_cfg.clocks[0].stage2_cfg.delay_metric,
+            Some(DelayMetric::D2M)
+        );
+    }
 }
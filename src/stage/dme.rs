@@ -0,0 +1,356 @@
+use crate::error::CtsError;
+use crate::model::*;
+
+// bisection steps of the merging delay in Elmore mode
+const BISECTION_STEPS: usize = 64;
+
+// Tilted rectangular region, an axis-aligned box in the coordinates rotated by 45 degree
+// (u = x + y, v = x - y), where manhattan distance is max(|du|, |dv|). A point, a merging
+// segment and a region within some distance of them are all TRRs.
+#[derive(Clone, Copy, Debug)]
+struct Trr {
+    u: (f64, f64),
+    v: (f64, f64),
+}
+
+impl Trr {
+    fn point(location: Location) -> Self {
+        let u = (location.0 + location.1) as f64;
+        let v = (location.0 - location.1) as f64;
+        Trr {
+            u: (u, u),
+            v: (v, v),
+        }
+    }
+
+    // intersection of the TRRs each expanded by its radius, it's empty when a lower bound
+    // is above the upper bound
+    fn intersect(trrs: &[Trr], radius: &[f64]) -> Trr {
+        let axis = |bound: fn(&Trr) -> (f64, f64)| {
+            let low = trrs
+                .iter()
+                .zip(radius)
+                .map(|(t, r)| bound(t).0 - r)
+                .fold(f64::MIN, f64::max);
+            let high = trrs
+                .iter()
+                .zip(radius)
+                .map(|(t, r)| bound(t).1 + r)
+                .fold(f64::MAX, f64::min);
+            (low, high)
+        };
+        Trr {
+            u: axis(|t| t.u),
+            v: axis(|t| t.v),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.u.0 > self.u.1 || self.v.0 > self.v.1
+    }
+
+    // point of the region closest to `location`, rounded to the grid
+    fn closest(&self, location: Location) -> Location {
+        let target = Trr::point(location);
+        let clamp = |x: f64, (low, high): (f64, f64)| x.max(low).min(high);
+        let u = clamp(target.u.0, self.u).round();
+        let mut v = clamp(target.v.0, self.v).round();
+        // x and y are integers only when u and v have the same parity
+        if (u - v).rem_euclid(2.0) != 0.0 {
+            v = if v + 1.0 <= self.v.1 {
+                v + 1.0
+            } else {
+                v - 1.0
+            };
+        }
+        (((u + v) / 2.0) as i32, ((u - v) / 2.0) as i32)
+    }
+}
+
+// bottom-up result of a subtree
+struct Merged {
+    region: Trr,
+    delay: f64, // path length or Elmore delay to the sinks
+    cap: f64,   // downstream capacitance including snaking wire
+}
+
+impl ClockTree {
+    /// Re-embed merge units with deferred-merge embedding (DME)
+    ///
+    /// Bottom-up, every merge unit gets the merging region where all its childs can be
+    /// reached with the same path length, or the same Elmore delay plus sink latency when
+    /// `wire` is given, using the least wire. A child closer than its wirelength is routed
+    /// with snaking. Buffers aren't inserted yet, so Elmore delay is balanced on the
+    /// unbuffered tree and the buffer delay of uneven loads is left to plan_skew.
+    /// Top-down, the root is placed in its region closest to the clock source and every
+    /// merge unit at the point of its region closest to its parent. MergeUnit's location,
+    /// common_length and extra_length are updated, and length_map keeps the longest wire of
+    /// each level. The topology from gen_topology is kept.
+    pub fn embed_dme(&mut self, wire: Option<&WireModel>) -> Result<(), CtsError> {
+        self.check_consistency()?;
+        // Elmore delay can't be balanced by wirelength without resistance and capacitance
+        let wire = wire.filter(|w| {
+            let (r, c) = w.get_length_rc(1.0);
+            r > 0.0 && c > 0.0
+        });
+        let mut regions = vec![None; self.merges.len()];
+        // pseudo sinks take the largest latency so that no wire is added for them
+        let dummy_latency = self
+            .sinks
+            .iter()
+            .filter(|s| !s.dummy)
+            .map(|s| s.latency as f64)
+            .fold(0.0, f64::max);
+        let root = self.merge_bottom_up(self.root_node_index, wire, dummy_latency, &mut regions);
+
+        let anchor = self.source.unwrap_or_else(|| {
+            let center = |t: (f64, f64)| (t.0 + t.1) / 2.0;
+            let (u, v) = (center(root.region.u), center(root.region.v));
+            (((u + v) / 2.0) as i32, ((u - v) / 2.0) as i32)
+        });
+        let mut stack = vec![(self.root_node_index, anchor)];
+        while let Some((nidx, parent)) = stack.pop() {
+            let midx = match self.get_node_merge_unit(nidx) {
+                Some(d) => d,
+                None => continue,
+            };
+            let location = regions[midx].map_or(parent, |t: Trr| t.closest(parent));
+            self.merges[midx].location = location;
+            for child in self.get_merge_unit_load_nodes(midx) {
+                stack.push((child, location));
+            }
+        }
+
+        self.length_map.clear();
+        let mut level_merges = vec![self.get_node_merge_unit(self.root_node_index)];
+        for level in 0..self.tree_level {
+            let mut longest = 0;
+            let mut next = vec![];
+            for midx in level_merges.into_iter().flatten() {
+                let merge = &self.merges[midx];
+                let extra = merge.extra_length.iter().max().cloned().unwrap_or(0);
+                longest = longest.max(merge.common_length + extra);
+                next.extend(
+                    self.get_merge_unit_load_nodes(midx)
+                        .map(|n| self.get_node_merge_unit(n)),
+                );
+            }
+            self.length_map.insert(level, longest);
+            level_merges = next;
+        }
+        let wirelength: u32 = self
+            .merges
+            .iter()
+            .flat_map(|m| (0..m.extra_length.len()).map(move |i| m.child_length(i)))
+            .sum();
+        let balance = if wire.is_some() {
+            "Elmore delay"
+        } else {
+            "path length"
+        };
+        info!(
+            "DME finished balanced on {}, estimated wirelength:{}",
+            balance, wirelength
+        );
+        Ok(())
+    }
+
+    // merging region, delay and capacitance of the node, and the wirelength of each child of
+    // the merge unit it drives
+    fn merge_bottom_up(
+        &mut self,
+        nidx: NodeIndex,
+        wire: Option<&WireModel>,
+        dummy_latency: f64,
+        regions: &mut Vec<Option<Trr>>,
+    ) -> Merged {
+        let midx = match self.nodes[nidx].node_owner {
+            NodeOwner::Sink(d) => {
+                let sink = &self.sinks[d];
+                // insertion delay of the generated clock tree below the sink is balanced
+                // in Elmore mode
+                let delay = match (wire, sink.dummy) {
+                    (Some(_), false) => sink.latency as f64,
+                    (Some(_), true) => dummy_latency,
+                    (None, _) => 0.0,
+                };
+                return Merged {
+                    region: Trr::point(sink.location),
+                    delay,
+                    cap: sink.cap as f64,
+                };
+            }
+            NodeOwner::Buffer(d) => self.buffers[d].load,
+            NodeOwner::MergeUnit(d) => d,
+        };
+        let childs: Vec<NodeIndex> = self.get_merge_unit_load_nodes(midx).collect();
+        let merged: Vec<Merged> = childs
+            .iter()
+            .map(|child| self.merge_bottom_up(*child, wire, dummy_latency, regions))
+            .collect();
+        let trrs: Vec<Trr> = merged.iter().map(|m| m.region).collect();
+
+        // wirelength of each child for the merging delay
+        let lengths = |delay: f64| -> Vec<f64> {
+            merged
+                .iter()
+                .map(|m| match wire {
+                    Some(wire) => {
+                        let (r, c) = wire.get_length_rc(1.0);
+                        solve_elmore_length(r as f64, c as f64, m.cap, delay - m.delay)
+                    }
+                    None => (delay - m.delay).max(0.0),
+                })
+                .collect()
+        };
+        let slowest = merged.iter().map(|m| m.delay).fold(f64::MIN, f64::max);
+        let delay = match wire {
+            None => {
+                // least delay where every pair of childs reaches each other
+                let axis = |bound: fn(&Trr) -> (f64, f64)| {
+                    let low = merged
+                        .iter()
+                        .map(|m| bound(&m.region).0 + m.delay)
+                        .fold(f64::MIN, f64::max);
+                    let high = merged
+                        .iter()
+                        .map(|m| bound(&m.region).1 - m.delay)
+                        .fold(f64::MAX, f64::min);
+                    (low - high) / 2.0
+                };
+                slowest.max(axis(|t| t.u)).max(axis(|t| t.v))
+            }
+            Some(_) => {
+                let feasible = |delay: f64| !Trr::intersect(&trrs, &lengths(delay)).is_empty();
+                let mut low = slowest;
+                let mut step = 1e-6_f64.max(slowest.abs());
+                while !feasible(low + step) {
+                    step *= 2.0;
+                }
+                let mut high = low + step;
+                for _ in 0..BISECTION_STEPS {
+                    let mid = (low + high) / 2.0;
+                    if feasible(mid) {
+                        high = mid;
+                    } else {
+                        low = mid;
+                    }
+                }
+                high
+            }
+        };
+        let child_lengths = lengths(delay);
+        let mut region = Trr::intersect(&trrs, &child_lengths);
+        // the region of the least delay is a segment, keep it valid under rounding error
+        for axis in [&mut region.u, &mut region.v] {
+            if axis.0 > axis.1 {
+                let mid = (axis.0 + axis.1) / 2.0;
+                *axis = (mid, mid);
+            }
+        }
+        regions[midx] = Some(region);
+
+        let rounded: Vec<u32> = child_lengths.iter().map(|l| l.round() as u32).collect();
+        let common_length = rounded.iter().min().cloned().unwrap_or(0);
+        let merge = &mut self.merges[midx];
+        merge.common_length = common_length;
+        merge.extra_length = rounded.iter().map(|l| l - common_length).collect();
+
+        let unit_cap = wire.map_or(0.0, |w| w.get_length_rc(1.0).1 as f64);
+        let cap = merged
+            .iter()
+            .zip(&child_lengths)
+            .map(|(m, l)| m.cap + unit_cap * l)
+            .sum();
+        Merged { region, delay, cap }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn build(locations: &[Location], caps: &[f32]) -> ClockTree {
+        let mut tree = new_tree(locations, caps);
+        tree.gen_topology(&topology_cfg(4)).unwrap();
+        tree
+    }
+
+    fn spread(values: &[u32]) -> u32 {
+        values.iter().max().unwrap() - values.iter().min().unwrap()
+    }
+
+    #[test]
+    fn two_sinks_merge_at_midpoint() {
+        let mut tree = build(&[(0, 0), (1000, 400)], &[0.001]);
+        tree.embed_dme(None).unwrap();
+        let root = &tree.merges[tree.get_node_merge_unit(tree.root_node_index).unwrap()];
+        assert_eq!(root.common_length, 700);
+        assert!(root.extra_length.iter().all(|d| *d == 0));
+        let distance = |d: Location| (d.0 - root.location.0).abs() + (d.1 - root.location.1).abs();
+        assert_eq!(distance((0, 0)), 700);
+        assert_eq!(distance((1000, 400)), 700);
+    }
+
+    #[test]
+    fn path_length_dme_is_zero_skew() {
+        let mut tree = build(&random_locations(7, 61, (0, 20000)), &[0.001]);
+        tree.embed_dme(None).unwrap();
+        tree.check_consistency().unwrap();
+        tree.routing().unwrap();
+        let lengths = sink_path_lengths(&tree);
+        assert_eq!(lengths.len(), 61);
+        // odd length of a detour and rounding to grid lose a unit per level
+        assert!(spread(&lengths) <= 2 * tree.tree_level as u32);
+
+        let mut midpoint = build(&random_locations(7, 61, (0, 20000)), &[0.001]);
+        midpoint.routing().unwrap();
+        assert!(spread(&sink_path_lengths(&midpoint)) > spread(&lengths));
+    }
+
+    fn elmore_skew(tree: &ClockTree, wire: &WireModel) -> (f32, f32) {
+        let lut = LutModel::new(vec![0.1], vec![0.1], vec![vec![0.1]]).unwrap();
+        let model = BufferModel {
+            area: 1.0,
+            input_cap: 0.001,
+            max_cap: None,
+            cell_rise: lut.clone(),
+            cell_fall: lut.clone(),
+            rise_transition: lut.clone(),
+            fall_transition: lut,
+        };
+        let buffer_lib = BufferLib::new(vec![("BUF".to_string(), model)]).unwrap();
+        let report = tree
+            .timing_analysis(&buffer_lib, wire, 0.05, 10.0, DelayMetric::Elmore)
+            .unwrap();
+        assert!(report.min_latency > 0.0);
+        (report.skew, report.max_latency)
+    }
+
+    #[test]
+    fn elmore_dme_is_zero_skew() {
+        let wire = new_wire();
+        let caps = [0.001, 0.004, 0.002, 0.008, 0.001];
+        let mut tree = build(&random_locations(11, 37, (0, 30000)), &caps);
+        tree.embed_dme(Some(&wire)).unwrap();
+        tree.routing().unwrap();
+        let (skew, max_latency) = elmore_skew(&tree, &wire);
+        assert!(skew < max_latency * 0.01);
+    }
+
+    #[test]
+    fn elmore_dme_balances_sink_latency() {
+        let wire = new_wire();
+        let mut tree = build(&random_locations(5, 29, (0, 30000)), &[0.002]);
+        for (i, sink) in tree.sinks.iter_mut().filter(|s| !s.dummy).enumerate() {
+            // insertion delay of generated clock trees below some sinks
+            sink.latency = [0.0, 0.0, 0.02, 0.0, 0.05][i % 5];
+        }
+        tree.embed_dme(Some(&wire)).unwrap();
+        tree.routing().unwrap();
+        let (skew, max_latency) = elmore_skew(&tree, &wire);
+        assert!(max_latency > 0.05);
+        assert!(skew < max_latency * 0.01);
+    }
+}
@@ -0,0 +1,673 @@
+use crate::model::*;
+
+use super::cfg::ExportCfg;
+use crate::plugin::{DesignPlugin, RoutedNet, WireSegment};
+use cts_plugin::Path;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+// a net of the synthesized clock tree, split at each buffer
+pub struct ClockNet {
+    pub name: String,
+    pub driver: Option<BufferIndex>, // None means driven by the clock source
+    pub loads: Vec<NodeIndex>,       // buffer or sink nodes
+    pub merges: Vec<MergeUnitIndex>, // merge units whose path is routed on this net
+}
+
+impl ClockTree {
+    /// Split the clock net at each buffer
+    ///
+    /// The first net keeps the clock net name and is driven by the clock source,
+    /// every buffer drives a new net named after the buffer.
+    pub fn get_clock_nets(&self) -> Vec<ClockNet> {
+        let mut nets = vec![ClockNet {
+            name: self.name.clone(),
+            driver: None,
+            loads: vec![],
+            merges: vec![],
+        }];
+        // (node, net driving the node)
+        let mut stack: Vec<(NodeIndex, usize)> = vec![(self.root_node_index, 0)];
+        while let Some((nidx, net)) = stack.pop() {
+            let (midx, net) = match self.nodes[nidx].node_owner {
+                NodeOwner::Sink(_) => {
+                    nets[net].loads.push(nidx);
+                    continue;
+                }
+                NodeOwner::Buffer(d) => {
+                    nets[net].loads.push(nidx);
+                    nets.push(ClockNet {
+                        name: format!("{}_net", self.buffers[d].buffer_name),
+                        driver: Some(d),
+                        loads: vec![],
+                        merges: vec![],
+                    });
+                    (self.buffers[d].load, nets.len() - 1)
+                }
+                NodeOwner::MergeUnit(d) => (d, net),
+            };
+            nets[net].merges.push(midx);
+            let childs: Vec<NodeIndex> = self.get_merge_unit_load_nodes(midx).collect();
+            for child in childs.into_iter().rev() {
+                stack.push((child, net));
+            }
+        }
+        nets
+    }
+
+    /// Components added by the synthesized clock tree as (name, cell, location)
+    ///
+    /// Every buffer is added, and every pseudo sink as a dummy load cell when dummy load
+    /// is exported.
+    pub fn get_new_components(&self, cfg: &ExportCfg) -> Vec<(String, String, Location)> {
+        let mut components: Vec<(String, String, Location)> = self
+            .buffers
+            .iter()
+            .map(|b| (b.buffer_name.clone(), b.model_name.clone(), b.location))
+            .collect();
+        if let Some(dummy_load) = cfg.dummy_load.as_ref() {
+            components.extend(
+                self.sinks
+                    .iter()
+                    .filter(|s| s.dummy)
+                    .map(|s| (s.name.clone(), dummy_load.cell.clone(), s.location)),
+            );
+        }
+        components
+    }
+
+    /// Routed nets replacing the clock net
+    ///
+    /// `clock_pins` are the (instance, pin) connections of the clock net in the design.
+    /// Each sink is connected with all its pins on the clock net to its driving net, and
+    /// the connections which are not part of the tree stay on the first net. The first net
+    /// is also routed along the trunk from the clock source.
+    pub fn get_routed_nets(
+        &self,
+        clock_pins: &[(String, String)],
+        cfg: &ExportCfg,
+    ) -> Result<Vec<RoutedNet>, Box<dyn Error>> {
+        let tree_sinks: HashSet<&str> = self
+            .sinks
+            .iter()
+            .filter(|s| !s.dummy)
+            .map(|s| s.name.as_str())
+            .collect();
+        let kept_pins = clock_pins
+            .iter()
+            .filter(|p| !tree_sinks.contains(p.0.as_str()))
+            .cloned();
+
+        let mut routed_nets = vec![];
+        for net in self.get_clock_nets() {
+            let mut pins: Vec<(String, String)> = match net.driver {
+                None => kept_pins.clone().collect(),
+                Some(d) => vec![(
+                    self.buffers[d].buffer_name.clone(),
+                    cfg.buffer_output_pin.clone(),
+                )],
+            };
+            for nidx in &net.loads {
+                match self.nodes[*nidx].node_owner {
+                    NodeOwner::Buffer(d) => pins.push((
+                        self.buffers[d].buffer_name.clone(),
+                        cfg.buffer_input_pin.clone(),
+                    )),
+                    // pseudo sink has no cell behind it unless dummy load is exported
+                    NodeOwner::Sink(d) if self.sinks[d].dummy => {
+                        if let Some(dummy_load) = cfg.dummy_load.as_ref() {
+                            pins.push((self.sinks[d].name.clone(), dummy_load.input_pin.clone()));
+                        }
+                    }
+                    NodeOwner::Sink(d) => {
+                        let sink = &self.sinks[d].name;
+                        let sink_pins: Vec<(String, String)> = clock_pins
+                            .iter()
+                            .filter(|p| &p.0 == sink)
+                            .cloned()
+                            .collect();
+                        if sink_pins.is_empty() {
+                            return Err(
+                                format!("sink {} is not on clock net {}", sink, self.name).into()
+                            );
+                        }
+                        pins.extend(sink_pins);
+                    }
+                    NodeOwner::MergeUnit(_) => {}
+                }
+            }
+            // the clock source drives the first net through the trunk
+            let trunk = match net.driver {
+                None => self.trunk.as_slice(),
+                Some(_) => &[],
+            };
+            let wires = trunk
+                .iter()
+                .chain(
+                    net.merges
+                        .iter()
+                        .flat_map(|midx| self.merges[*midx].path.iter()),
+                )
+                .flat_map(|path| path_wires(path, cfg))
+                .collect();
+            routed_nets.push(RoutedNet {
+                name: net.name,
+                pins,
+                wires,
+            });
+        }
+        Ok(routed_nets)
+    }
+
+    /// Write the buffered gate-level netlist text
+    ///
+    /// Only the module declaring the clock net is rewritten: the clock pin of each sink
+    /// in it is reconnected to the net of its driving buffer, and the buffers with their
+    /// nets are added to the module. DEF names are written as Verilog identifiers.
+    pub fn write_verilog(&self, verilog: &str, cfg: &ExportCfg) -> Result<String, Box<dyn Error>> {
+        let nets = self.get_clock_nets();
+        let net_names: Vec<String> = nets.iter().map(|n| verilog_identifier(&n.name)).collect();
+        // <sink instance, driving net> and <buffer, driving net> mapping
+        let mut sink_net: HashMap<String, &str> = HashMap::new();
+        let mut buffer_net: HashMap<BufferIndex, &str> = HashMap::new();
+        let mut dummy_net: Vec<(SinkIndex, &str)> = vec![];
+        for (net, name) in nets.iter().zip(&net_names) {
+            for nidx in &net.loads {
+                match self.nodes[*nidx].node_owner {
+                    NodeOwner::Sink(d) if self.sinks[d].dummy => {
+                        dummy_net.push((d, name));
+                    }
+                    NodeOwner::Sink(d) => {
+                        sink_net.insert(verilog_identifier(&self.sinks[d].name), name);
+                    }
+                    NodeOwner::Buffer(d) => {
+                        buffer_net.insert(d, name);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let stmts = split_statements(verilog);
+        let words: Vec<Vec<(usize, &str)>> = stmts.iter().map(|s| get_words(s)).collect();
+        // keyword of a statement, or the cell name of an instance
+        let keyword = |i: usize| words[i].iter().find(|w| w.0 == 0).map_or("", |w| w.1);
+        let is_decl = |i: usize| {
+            matches!(
+                keyword(i),
+                "module"
+                    | "input"
+                    | "output"
+                    | "inout"
+                    | "wire"
+                    | "reg"
+                    | "assign"
+                    | "supply0"
+                    | "supply1"
+            )
+        };
+        // instance name follows the cell name, after the parameters of the cell if any
+        let sink_net_of = |i: usize| match is_decl(i) {
+            true => None,
+            false => words[i]
+                .iter()
+                .filter(|w| w.0 == 0)
+                .nth(1)
+                .and_then(|inst| sink_net.get(inst.1)),
+        };
+        // (module statement, endmodule statement) of the module declaring the clock net,
+        // the one with the most sinks when a submodule has a net of the same name
+        let clock_net = &net_names[0];
+        let declared = match clock_net.starts_with('\\') {
+            true => clock_net.as_str(),
+            false => clock_net.split('[').next().unwrap_or(clock_net),
+        };
+        let mut module: Option<(usize, usize, usize)> = None;
+        let mut first = 0;
+        for i in 0..stmts.len() {
+            match keyword(i) {
+                "module" => first = i,
+                "endmodule" => {
+                    let declares = |j: usize| {
+                        matches!(keyword(j), "module" | "input" | "output" | "inout" | "wire")
+                            && words[j].iter().any(|w| w.1 == declared)
+                    };
+                    let sinks = (first..i).filter(|j| sink_net_of(*j).is_some()).count();
+                    let more_sinks = !matches!(module, Some(m) if m.2 >= sinks);
+                    if (first..i).any(declares) && more_sinks {
+                        module = Some((first, i, sinks));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let (first, last, _) =
+            module.ok_or_else(|| format!("clock net {} is not declared in verilog", self.name))?;
+
+        let mut result = String::new();
+        let mut wire_declared = false;
+        for (i, stmt) in stmts.iter().enumerate() {
+            if i < first || i > last {
+                result.push_str(stmt);
+                continue;
+            }
+            if !is_decl(i) && !wire_declared {
+                // new nets are declared before the first cell instance
+                for net in net_names.iter().skip(1) {
+                    result.push_str(&format!("\nwire {} ;", net));
+                }
+                wire_declared = true;
+            }
+            if i == last {
+                let cells = self.get_cell_text(&nets, &net_names, &buffer_net, &dummy_net, cfg);
+                result.push_str(&cells);
+            }
+            match sink_net_of(i) {
+                Some(net) => result.push_str(&replace_identifier(stmt, clock_net, net)),
+                _ => result.push_str(stmt),
+            }
+        }
+        Ok(result)
+    }
+
+    // instances of the buffers, and of the pseudo sinks when dummy load is exported
+    fn get_cell_text(
+        &self,
+        nets: &[ClockNet],
+        net_names: &[String],
+        buffer_net: &HashMap<BufferIndex, &str>,
+        dummy_net: &[(SinkIndex, &str)],
+        cfg: &ExportCfg,
+    ) -> String {
+        let mut result = String::new();
+        for (net, name) in nets.iter().zip(net_names).skip(1) {
+            let d = net.driver.unwrap();
+            let buffer = &self.buffers[d];
+            result.push_str(&format!(
+                "\n{} {} (.{} ( {} ) , .{} ( {} ) ) ;",
+                buffer.model_name,
+                verilog_identifier(&buffer.buffer_name),
+                cfg.buffer_input_pin,
+                buffer_net[&d],
+                cfg.buffer_output_pin,
+                name
+            ));
+        }
+        if let Some(dummy_load) = cfg.dummy_load.as_ref() {
+            for (d, net) in dummy_net {
+                result.push_str(&format!(
+                    "\n{} {} (.{} ( {} ) ) ;",
+                    dummy_load.cell,
+                    verilog_identifier(&self.sinks[*d].name),
+                    dummy_load.input_pin,
+                    net
+                ));
+            }
+        }
+        result
+    }
+}
+
+/// Write all synthesized clock trees into one DEF
+///
+/// The buffers and routed nets of every clock tree are put into the design through the
+/// plugin, which writes the DEF to `cfg.def_path`.
+pub fn export_def(
+    trees: &[ClockTree],
+    plugin: &mut dyn DesignPlugin,
+    cfg: &ExportCfg,
+) -> Result<(), Box<dyn Error>> {
+    for tree in trees {
+        let clock_pins = plugin.get_net_pins(&tree.name)?;
+        for (name, cell, location) in tree.get_new_components(cfg) {
+            plugin.add_component(&name, &cell, location)?;
+        }
+        for net in tree.get_routed_nets(&clock_pins, cfg)? {
+            plugin.put_net(&net)?;
+        }
+    }
+    plugin.export_def(&cfg.def_path)?;
+    info!("export DEF to {}", cfg.def_path);
+    Ok(())
+}
+
+/// Write all synthesized clock trees into one netlist
+///
+/// The netlist at `verilog_in` is copied to `cfg.verilog_path` with every clock tree
+/// written in turn.
+pub fn export_verilog(
+    trees: &[ClockTree],
+    verilog_in: &str,
+    cfg: &ExportCfg,
+) -> Result<(), Box<dyn Error>> {
+    let mut verilog = std::fs::read_to_string(verilog_in)?;
+    for tree in trees {
+        verilog = tree.write_verilog(&verilog, cfg)?;
+    }
+    std::fs::write(&cfg.verilog_path, verilog)?;
+    info!("export verilog to {}", cfg.verilog_path);
+    Ok(())
+}
+
+// Replace whole identifier `from` with `to`, used to reconnect a pin in an instance
+// statement. An escaped identifier ends at white space, so one is kept after it.
+fn replace_identifier(stmt: &str, from: &str, to: &str) -> String {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let ends_ident = |c: char| match from.starts_with('\\') {
+        true => c.is_whitespace(),
+        false => !is_ident(c),
+    };
+    let mut result = String::new();
+    let mut rest = stmt;
+    while let Some(pos) = rest.find(from) {
+        let before = rest[..pos].chars().last();
+        let after = rest[pos + from.len()..].chars().next();
+        result.push_str(&rest[..pos]);
+        if before.is_some_and(is_ident) || after.is_some_and(|c| !ends_ident(c)) {
+            result.push_str(from);
+        } else {
+            result.push_str(to);
+            if to.starts_with('\\') && !after.is_some_and(char::is_whitespace) {
+                result.push(' ');
+            }
+        }
+        rest = &rest[pos + from.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// Verilog identifier of a DEF name. Plain identifiers and bit selects are kept, other
+// names are escaped, and the escapes of DEF are dropped.
+fn verilog_identifier(def_name: &str) -> String {
+    let is_plain = |s: &str| {
+        s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+    };
+    let is_bit_select = def_name
+        .strip_suffix(']')
+        .and_then(|s| s.split_once('['))
+        .is_some_and(|(base, bit)| {
+            is_plain(base) && !bit.is_empty() && bit.chars().all(|c| c.is_ascii_digit())
+        });
+    if is_plain(def_name) || is_bit_select {
+        def_name.to_string()
+    } else {
+        format!("\\{}", def_name.replace('\\', ""))
+    }
+}
+
+// Lexical tokens of verilog text as byte ranges: identifiers, numbers and single
+// punctuation characters. White space, comments and strings are skipped, and an escaped
+// identifier keeps its leading backslash.
+fn tokenize(text: &str) -> Vec<(usize, usize)> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    while let Some(c) = text[start..].chars().next() {
+        let rest = &text[start..];
+        let len = if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            comment.find("*/").map_or(rest.len(), |end| end + 4)
+        } else if c == '"' {
+            rest[1..].find('"').map_or(rest.len(), |end| end + 2)
+        } else if c.is_whitespace() {
+            c.len_utf8()
+        } else {
+            let len = if c == '\\' {
+                rest.find(char::is_whitespace).unwrap_or(rest.len())
+            } else if c.is_alphanumeric() || c == '_' || c == '\'' {
+                rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$' || c == '\''))
+                    .unwrap_or(rest.len())
+            } else {
+                c.len_utf8()
+            };
+            tokens.push((start, start + len));
+            len
+        };
+        start += len;
+    }
+    tokens
+}
+
+// Statements of verilog text, each ends with `;` or `endmodule`. The text after the last
+// statement is kept as one more piece.
+fn split_statements(text: &str) -> Vec<&str> {
+    let mut stmts = vec![];
+    let mut start = 0;
+    for (from, to) in tokenize(text) {
+        if matches!(&text[from..to], ";" | "endmodule") {
+            stmts.push(&text[start..to]);
+            start = to;
+        }
+    }
+    if start < text.len() {
+        stmts.push(&text[start..]);
+    }
+    stmts
+}
+
+// identifiers of a statement with their parenthesis depth
+fn get_words(stmt: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut depth: usize = 0;
+    for (from, to) in tokenize(stmt) {
+        let token = &stmt[from..to];
+        match token {
+            "(" => depth += 1,
+            ")" => depth = depth.saturating_sub(1),
+            _ if token.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '\\') => {
+                words.push((depth, token))
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+// one wire per straight segment, horizontal and vertical wires on their own layer
+fn path_wires(path: &Path, cfg: &ExportCfg) -> Vec<WireSegment> {
+    let mut points = vec![path.from];
+    if let Some(turn) = path.turn {
+        points.push(turn);
+    }
+    points.push(path.to);
+    let segments: Vec<(Location, Location)> = points
+        .windows(2)
+        .filter(|p| p[0] != p[1])
+        .map(|p| (p[0], p[1]))
+        .collect();
+    let mut wires = vec![];
+    for (i, (a, b)) in segments.iter().enumerate() {
+        let horizontal = a.1 == b.1;
+        let layer = if horizontal {
+            &cfg.horizontal_layer
+        } else {
+            &cfg.vertical_layer
+        };
+        let via = match (&cfg.via_name, segments.get(i + 1)) {
+            (Some(via), Some(next)) if horizontal != (next.0 .1 == next.1 .1) => Some(via.clone()),
+            _ => None,
+        };
+        wires.push(WireSegment {
+            layer: layer.clone(),
+            from: *a,
+            to: *b,
+            via,
+        });
+    }
+    wires
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::topology_cfg;
+
+    fn new_cfg() -> ExportCfg {
+        ExportCfg {
+            def_path: "out.def".to_string(),
+            verilog_path: "out.v".to_string(),
+            buffer_input_pin: "A".to_string(),
+            buffer_output_pin: "Y".to_string(),
+            horizontal_layer: "metal3".to_string(),
+            vertical_layer: "metal2".to_string(),
+            via_name: Some("via2".to_string()),
+            dummy_load: None,
+        }
+    }
+
+    fn build() -> ClockTree {
+        let mut tree = ClockTree::builder("clk")
+            .sink("ff_0", (0, 0), 0.001)
+            .sink("ff_1", (1000, 0), 0.001)
+            .sink("ff_2", (0, 1000), 0.001)
+            .sink("ff_3", (1000, 1000), 0.001)
+            .build()
+            .unwrap();
+        tree.gen_topology(&topology_cfg(4)).unwrap();
+        tree.routing().unwrap();
+        tree
+    }
+
+    fn pin(inst: &str, pin: &str) -> (String, String) {
+        (inst.to_string(), pin.to_string())
+    }
+
+    #[test]
+    fn routed_nets_keep_every_clock_pin() {
+        let tree = build();
+        let mut clock_pins: Vec<(String, String)> =
+            (0..4).map(|i| pin(&format!("ff_{}", i), "CK")).collect();
+        // dual clock pins on one instance and a load outside the tree
+        clock_pins.push(pin("ff_2", "CKB"));
+        clock_pins.push(pin("PIN", "clk"));
+        clock_pins.push(pin("latch_0", "G"));
+        let nets = tree.get_routed_nets(&clock_pins, &new_cfg()).unwrap();
+        assert_eq!(nets.len(), 1);
+        let mut pins = nets[0].pins.clone();
+        pins.sort();
+        clock_pins.sort();
+        assert_eq!(pins, clock_pins);
+        assert!(!nets[0].wires.is_empty());
+        assert!(nets[0]
+            .wires
+            .iter()
+            .all(|w| w.from.0 == w.to.0 || w.from.1 == w.to.1));
+
+        clock_pins.retain(|p| p.0 != "ff_3");
+        assert!(tree.get_routed_nets(&clock_pins, &new_cfg()).is_err());
+    }
+
+    #[test]
+    fn trunk_is_exported() {
+        let mut tree = build();
+        tree.source = Some((3000, -500));
+        tree.routing().unwrap();
+        let root = tree.get_node_location(tree.root_node_index);
+        let route = tree.get_trunk_route();
+        assert_eq!(route.first().unwrap().0, (3000, -500));
+        assert_eq!(route.last().unwrap().1, root);
+        let length: i32 = tree.trunk.iter().map(|d| d.length()).sum();
+        assert_eq!(length, (3000 - root.0).abs() + (root.1 + 500).abs());
+
+        let clock_pins: Vec<(String, String)> =
+            (0..4).map(|i| pin(&format!("ff_{}", i), "CK")).collect();
+        let nets = tree.get_routed_nets(&clock_pins, &new_cfg()).unwrap();
+        let from_source: Vec<&WireSegment> = nets[0]
+            .wires
+            .iter()
+            .filter(|w| w.from == (3000, -500))
+            .collect();
+        assert_eq!(from_source.len(), 1);
+        assert_eq!(from_source[0].layer, "metal2");
+    }
+
+    #[test]
+    fn replace_whole_identifier() {
+        let stmt = "DFF ff_0 (.CK(clk), .D(clk_en), .Q(gclk) ) ;";
+        assert_eq!(
+            replace_identifier(stmt, "clk", "n1"),
+            "DFF ff_0 (.CK(n1), .D(clk_en), .Q(gclk) ) ;"
+        );
+        let stmt = "DFF ff_0 (.CK(\\u1/clk ), .D(\\u1/clk2 ) ) ;";
+        assert_eq!(
+            replace_identifier(stmt, "\\u1/clk", "n1"),
+            "DFF ff_0 (.CK(n1 ), .D(\\u1/clk2 ) ) ;"
+        );
+        assert_eq!(
+            replace_identifier("DFF ff_0 (.CK(clk[0]));", "clk[0]", "\\u1/n1"),
+            "DFF ff_0 (.CK(\\u1/n1 ));"
+        );
+    }
+
+    #[test]
+    fn verilog_identifiers() {
+        assert_eq!(verilog_identifier("clk"), "clk");
+        assert_eq!(verilog_identifier("clk[3]"), "clk[3]");
+        assert_eq!(verilog_identifier("u1/clk"), "\\u1/clk");
+        assert_eq!(verilog_identifier("clk\\[3\\]"), "\\clk[3]");
+    }
+
+    const NETLIST: &str = "module sub ( clk , q ) ;
+input clk ; // clocked; not by the tree
+output q ;
+DFF ff_0 ( .CK ( clk ) , .Q ( q ) ) ;
+endmodule
+
+module top ( clk ) ;
+input clk ;
+wire q ;
+/* sinks of clk; ff_3 is parameterized */
+DFF ff_0 ( .CK ( clk ) , .Q ( q ) ) ;
+DFF ff_1 ( .CK ( clk ) ) ;
+DFF ff_2 ( .CK ( clk ) ) ;
+DFFP #( .INIT ( 1'b0 ) ) ff_3 ( .CK ( clk ) ) ;
+sub u0 ( .clk ( clk ) , .q ( q ) ) ;
+endmodule
+";
+
+    #[test]
+    fn verilog_rewrites_clock_module() {
+        let mut tree = build();
+        tree.gen_topology(&topology_cfg(2)).unwrap();
+        let root = tree.get_node_merge_unit(tree.root_node_index).unwrap();
+        let halves: Vec<NodeIndex> = tree.get_merge_unit_load_nodes(root).collect();
+        for (i, nidx) in halves.into_iter().enumerate() {
+            tree.insert_buffer("BUF_X1", i, nidx).unwrap();
+        }
+        let verilog = tree.write_verilog(NETLIST, &new_cfg()).unwrap();
+
+        let (sub, top) = verilog.split_at(verilog.find("module top").unwrap());
+        assert_eq!(sub, NETLIST.split_at(NETLIST.find("module top").unwrap()).0);
+        assert!(top.contains("sub u0 ( .clk ( clk ) , .q ( q ) ) ;"));
+        assert!(top.ends_with(") ) ;\nendmodule\n"));
+        // every sink, the parameterized one too, moves to the net of its buffer
+        assert!(!top.contains(".CK ( clk )"));
+        for net in tree.get_clock_nets().iter().skip(1) {
+            let buffer = &tree.buffers[net.driver.unwrap()].buffer_name;
+            assert!(top.contains(&format!(
+                "wire q ;\nwire {} ;",
+                tree.get_clock_nets()[1].name
+            )));
+            assert!(top.contains(&format!(
+                "BUF_X1 {} (.A ( clk ) , .Y ( {} ) ) ;",
+                buffer, net.name
+            )));
+            assert_eq!(net.loads.len(), 2);
+            for nidx in &net.loads {
+                let sink = match tree.nodes[*nidx].node_owner {
+                    NodeOwner::Sink(d) => &tree.sinks[d].name,
+                    _ => panic!("buffer doesn't drive a sink"),
+                };
+                let inst = format!(" {} (", sink);
+                let stmt = top.lines().find(|l| l.contains(&inst)).unwrap();
+                assert!(stmt.contains(&format!(".CK ( {} )", net.name)), "{}", stmt);
+            }
+        }
+
+        tree.name = "clk2".to_string();
+        assert!(tree.write_verilog(NETLIST, &new_cfg()).is_err());
+    }
+}
@@ -1,226 +1,620 @@
+use super::super::error::CtsError;
 use super::super::model::*;
-use super::cfg::GenTopologyCfg;
+use super::cfg::{GenTopologyCfg, GroupBalance, GroupingStrategy};
+use super::routing::is_inside;
 use core::f32::consts::PI;
-use rand::Rng;
-type GroupLabel = u32;
+use std::collections::HashMap;
+
+// pitch of the candidate locations of a pseudo sink around its anchor in DBU, a pseudo
+// sink is at least this far from every sink in x or y so that its dummy load doesn't
+// overlap them
+const DUMMY_PITCH: i32 = 400;
+
+// bounds of branch number planning, see `plan_branches`
+const MAX_EXTRA_LEVELS: usize = 1;
+const MAX_LEAF_RATIO: f32 = 1.25;
+const MAX_PLAN_NODES: usize = 100_000;
+
+// sinks whose requested arrivals are apart by more than this share of their spread are
+// in different offset classes, see `get_offset_classes`
+const OFFSET_GAP_RATIO: f32 = 0.1;
 
 impl ClockTree {
     /// Two things happened in this stage
     ///
     /// 1. After BNP(branch number planning), fanout_map is updated.
     ///    If there needs to insert pseudo sink, then ClockTree's sink field is updated
-    /// 2. After Tree construction, ClockTree's merges , nodes and fanout_map field are first time updated
-
-    pub fn gen_topology(&mut self, cfg: &GenTopologyCfg) {
-        let mut branchs = vec![];
-        let mut n = self.sinks.len();
-        while n > 1 {
-            for i in 2..=cfg.max_branch {
-                if n % i == 0 {
-                    n /= i;
-                    branchs.push(i as u32);
-                    break;
-                } else if i == cfg.max_branch {
-                    n += 1;
-                }
-            }
+    /// 2. After Tree construction, ClockTree's merges , nodes and length_map field are first time updated,
+    ///    root_node_index is set and the tree is verified by `check_consistency`
+    pub fn gen_topology(&mut self, cfg: &GenTopologyCfg) -> Result<(), CtsError> {
+        if self.sinks.is_empty() {
+            return Err(CtsError::EmptySinks);
         }
+        // topology is built from scratch
+        self.sinks.retain(|s| !s.dummy);
+        self.dummy_count = 0;
+        self.nodes.clear();
+        self.merges.clear();
+        self.buffers.clear();
+        self.length_map.clear();
+        self.fanout_map.clear();
+        self.trunk.clear();
+        let branchs = self.plan_branches(cfg)?;
         for (i, b) in branchs.iter().enumerate() {
-            self.fanout_map.insert(i, *b as u32);
+            self.fanout_map.insert(i, *b);
         }
         self.tree_level = branchs.len() as ClockTreeLevel;
-        let target_num = branchs.iter().fold(1, |acc, x| acc * x) as usize;
-        let pseudo_sink = target_num - self.sinks.len();
-        if pseudo_sink != 0 {
-            let mut rng = rand::thread_rng();
-            for _ in 0..pseudo_sink {
-                let x = rng.gen_range(self.x_range.0..self.x_range.1);
-                let y = rng.gen_range(self.y_range.0..self.y_range.1);
-                self.sinks.push(Sink {
-                    name: String::from(""), // empty name
-                    location: (x, y),
-                })
-            }
-        }
+        let target_num = branchs.iter().product::<u32>() as usize;
         let coords: Vec<Location> = self.sinks.iter().map(|s| s.location).collect();
-        let grp2id: Vec<(GroupLabel, SinkIndex)> = group(&coords, &branchs);
-
-        let mut childs: Vec<SinkIndex> = grp2id.iter().map(|d| d.1).collect();
+        let balance = cfg.balance.unwrap_or(GroupBalance::Capacitance);
+        let mut weights = match balance {
+            GroupBalance::Count => Weights {
+                sinks: vec![1.0; coords.len()],
+                pseudo: 0.0,
+            },
+            GroupBalance::Capacitance => Weights {
+                sinks: self.sinks.iter().map(|s| s.cap.max(0.0)).collect(),
+                pseudo: self.dummy_cap.max(0.0),
+            },
+        };
+        if weights.sinks.iter().sum::<f32>() <= 0.0 {
+            // no capacitance from liberty, fall back to sink count
+            weights = Weights {
+                sinks: vec![1.0; coords.len()],
+                pseudo: 0.0,
+            };
+        }
+        let classes = get_offset_classes(&self.sinks);
+        let grouping = cfg.grouping.unwrap_or(GroupingStrategy::Angular);
+        let leaves: Vec<Leaf> = match grouping {
+            GroupingStrategy::Angular => group(&coords, &classes, &weights, &branchs, target_num),
+            GroupingStrategy::Bipartition => {
+                group_by_bipartition(&coords, &classes, &weights, &branchs, target_num)
+            }
+        };
 
-        // store roots location of nodes in same level when merging
-        let mut new_childs: Vec<MergeUnitIndex> = Vec::new();
+        let mut occupancy = Occupancy::new(&coords);
+        let mut childs: Vec<usize> = leaves
+            .into_iter()
+            .map(|leaf| match leaf {
+                Leaf::Sink(d) => d,
+                Leaf::Pseudo(anchor) => {
+                    let location = occupancy.find_free(anchor, self.die_area);
+                    occupancy.insert(location);
+                    self.add_dummy_sink(location)
+                }
+            })
+            .collect();
 
-        // reverse branchs to bottom-up order
-        // hashmap to store map between merge level and target wirelength
-        branchs.reverse();
-        let mut nidx: NodeIndex = 0;
-        let mut midx: MergeUnitIndex = 0;
-        let mut one_merge_child_location: Vec<Location> = Vec::new();
-        // construct merge unit
-        for (i, b) in branchs.iter().enumerate() {
-            let level = self.tree_level - i;
+        // construct merge units bottom-up, every `b` consecutive childs of a level are
+        // merged into one merge unit of the level above. Childs of the lowest level are
+        // sinks, and childs of upper levels are merge units built in the last round.
+        for (i, b) in branchs.iter().rev().enumerate() {
+            let level = self.tree_level - 1 - i;
             let mut target_len = u32::MIN;
-            if i == 0 {
-                for (j, s) in childs.iter().enumerate() {
-                    let n = j as u32;
-                    one_merge_child_location.push(self.sinks[*s].location);
-                    if (n + 1) & *b == 0 {
-                        let mut one_merge_inst = MergeUnit {
-                            first_node: nidx,
-                            ..Default::default()
-                        };
-                        self.nodes.push(Node {
-                            node_owner: NodeOwner::Sink(*s),
-                            next_node: None,
-                        });
-                        nidx += 1;
-                        one_merge_inst.analyze_child_location(&one_merge_child_location);
-                        one_merge_child_location.clear();
-                        // one_merge_inst.set_level(level);
-                        // get target length in current tree level by comparing bewteen same level of MergeUnit
-                        if one_merge_inst.common_length > target_len {
-                            target_len = one_merge_inst.common_length;
-                        }
-                        new_childs.push(midx);
-                        self.merges.push(one_merge_inst);
-                        midx += 1;
+            let mut new_childs: Vec<MergeUnitIndex> = Vec::new();
+            for chunk in childs.chunks(*b as usize) {
+                let first_node = self.nodes.len();
+                let mut one_merge_child_location: Vec<Location> = Vec::new();
+                for (j, d) in chunk.iter().enumerate() {
+                    let node_owner = if i == 0 {
+                        NodeOwner::Sink(*d)
                     } else {
-                        self.nodes.push(Node {
-                            node_owner: NodeOwner::Sink(*s),
-                            next_node: Some(nidx + 1),
-                        });
-                        nidx += 1;
-                    }
+                        NodeOwner::MergeUnit(*d)
+                    };
+                    self.nodes.push(Node {
+                        node_owner,
+                        next_node: if j + 1 < chunk.len() {
+                            Some(first_node + j + 1)
+                        } else {
+                            None
+                        },
+                    });
+                    one_merge_child_location.push(self.get_node_location(first_node + j));
                 }
-            } else {
-                for (j, s) in childs.iter().enumerate() {
-                    let n = j as u32;
-                    one_merge_child_location.push(self.sinks[*s].location);
-                    if (n + 1) & *b == 0 {
-                        let mut one_merge_inst = MergeUnit {
-                            first_node: nidx,
-                            ..Default::default()
-                        };
-                        self.nodes.push(Node {
-                            node_owner: NodeOwner::MergeUnit(*s),
-                            next_node: None,
-                        });
-                        nidx += 1;
-                        one_merge_inst.analyze_child_location(&one_merge_child_location);
-                        one_merge_child_location.clear();
-                        // one_merge_inst.set_level(level);
-                        // get target length in current tree level by comparing bewteen same level of MergeUnit
-                        if one_merge_inst.common_length > target_len {
-                            target_len = one_merge_inst.common_length;
-                        }
-                        new_childs.push(midx);
-                        self.merges.push(one_merge_inst);
-                        midx += 1;
-                    } else {
-                        self.nodes.push(Node {
-                            node_owner: NodeOwner::MergeUnit(*s),
-                            next_node: Some(nidx + 1),
-                        });
-                        nidx += 1;
-                    }
+                let mut one_merge_inst = MergeUnit {
+                    first_node,
+                    ..Default::default()
+                };
+                one_merge_inst.analyze_child_location(&one_merge_child_location);
+                // get target length in current tree level by comparing bewteen same level of MergeUnit
+                if one_merge_inst.common_length > target_len {
+                    target_len = one_merge_inst.common_length;
                 }
+                new_childs.push(self.merges.len());
+                self.merges.push(one_merge_inst);
+            }
+            // childs of the same level are routed with the same length
+            for midx in &new_childs {
+                self.merges[*midx].common_length = target_len;
             }
             self.length_map.insert(level, target_len);
-
-            childs = new_childs.clone();
-            new_childs.clear();
+            childs = new_childs;
         }
+        // root node drives the merge unit of level 0
+        let root_merge = childs
+            .first()
+            .cloned()
+            .ok_or_else(|| CtsError::BadTopology("no merge unit is built".to_string()))?;
+        self.root_node_index = self.nodes.len();
+        self.nodes.push(Node {
+            node_owner: NodeOwner::MergeUnit(root_merge),
+            next_node: None,
+        });
+        self.check_consistency()?;
 
         let mut total_estimate_wire = 0;
         let mut fanout_mul = 1;
         for level in 0..self.tree_level {
-            fanout_mul *= self.fanout_map.get(&level).unwrap();
-            total_estimate_wire += self.length_map.get(&level).unwrap() * fanout_mul;
+            let missing = || CtsError::BadTopology(format!("level {} is not merged", level));
+            fanout_mul *= self.fanout_map.get(&level).ok_or_else(missing)?;
+            total_estimate_wire += self.length_map.get(&level).ok_or_else(missing)? * fanout_mul;
         }
 
-        println!(
-            "pre-merge finished, estimated wirelength:{}",
-            total_estimate_wire
+        info!(
+            "pre-merge finished with {:?} grouping balanced on {:?}, estimated wirelength:{}",
+            grouping, balance, total_estimate_wire
         );
+        Ok(())
     }
-}
 
-fn group(coords: &[(i32, i32)], branchs: &Vec<u32>) -> Vec<(u32, usize)> {
-    let mut result = Vec::new();
-    let mut grps = Vec::new();
-    let center = get_center(coords);
-    for (i, b) in branchs.iter().enumerate() {
-        if i == 0 {
-            let idxs = (0..coords.len()).collect();
-            grps = find_group(coords, center, (0, idxs), *b);
-        } else if i < branchs.len() - 1 {
-            let mut new_d = Vec::new();
-            for d in &grps {
-                let next_grps = find_group(coords, center, d.clone(), *b);
-
-                for g in next_grps {
-                    new_d.push(g);
+    // BNP(branch number planning): enumerate fanout multisets bounded by min_branch and
+    // max_branch whose product just covers the sink number, try each in descending and
+    // ascending top-down order, and keep the plan of least estimated cost. The search is
+    // bounded: a plan has at most MAX_EXTRA_LEVELS more levels than the fewest possible,
+    // plans with more than MAX_LEAF_RATIO leaves per sink are only kept as a fallback, and
+    // at most MAX_PLAN_NODES partial plans are visited, largest fanouts first.
+    fn plan_branches(&self, cfg: &GenTopologyCfg) -> Result<Vec<u32>, CtsError> {
+        cfg.check()?;
+        let min_branch = cfg.min_branch.unwrap_or(2);
+        let n = self.sinks.len();
+        let mut min_levels = 1;
+        while cfg.max_branch.saturating_pow(min_levels as u32) < n {
+            min_levels += 1;
+        }
+        let max_levels = min_levels + MAX_EXTRA_LEVELS;
+        let max_leaves = (n as f32 * MAX_LEAF_RATIO) as usize;
+        let mut candidates: Vec<Vec<u32>> = vec![];
+        // plan of the fewest leaves when every plan wastes too many leaves
+        let mut fallback: Option<(usize, Vec<u32>)> = None;
+        let mut stack: Vec<(usize, Vec<u32>)> = vec![(1, vec![])];
+        let mut visited = 0;
+        while let Some((product, branchs)) = stack.pop() {
+            visited += 1;
+            if visited > MAX_PLAN_NODES {
+                detail!(
+                    "branch planning stops after {} partial plans",
+                    MAX_PLAN_NODES
+                );
+                break;
+            }
+            if product >= n && !branchs.is_empty() {
+                // drop plans with a redundant level, a single sink still needs one level
+                let smallest = branchs.last().cloned().unwrap_or(1) as usize;
+                if branchs.len() > 1 && product / smallest >= n {
+                    continue;
+                }
+                if product <= max_leaves {
+                    candidates.push(branchs);
+                } else if !matches!(&fallback, Some((p, _)) if *p <= product) {
+                    fallback = Some((product, branchs));
                 }
+                continue;
             }
-            grps = new_d;
-        } else {
-            for d in &grps {
-                let next_grps = find_group(coords, center, d.clone(), *b);
-                for v in next_grps {
-                    // Vec<(u32,Vec<usize>)> become Vec<(u32,vec![usize])>
-                    result.push((v.0, v.1[0]));
+            // non-increasing so that each multiset is visited once, and the fanouts left
+            // must still be able to cover the sinks within max_levels
+            let largest = branchs.last().map_or(cfg.max_branch, |b| *b as usize);
+            let levels_left = (max_levels - branchs.len()) as u32;
+            if product.saturating_mul(largest.saturating_pow(levels_left)) < n {
+                continue;
+            }
+            for b in min_branch..=largest {
+                let mut next = branchs.clone();
+                next.push(b as u32);
+                stack.push((product * b, next));
+            }
+        }
+        if candidates.is_empty() {
+            candidates.extend(fallback.map(|(_, branchs)| branchs));
+        }
+
+        let mut best: Option<(f32, Vec<u32>)> = None;
+        for descending in candidates {
+            let mut ascending = descending.clone();
+            ascending.reverse();
+            for branchs in [descending, ascending] {
+                let cost = self.estimate_plan_cost(&branchs);
+                let better = match &best {
+                    Some(d) => cost < d.0,
+                    None => true,
+                };
+                if better {
+                    best = Some((cost, branchs));
                 }
             }
         }
+        let (cost, branchs) =
+            best.ok_or_else(|| CtsError::BadTopology("no branch plan is found".to_string()))?;
+        detail!("branch plan:{:?}, estimated cost:{}", branchs, cost);
+        Ok(branchs)
     }
-    result
+
+    // Cost of a top-down fanout plan in DBU. Groups of a level evenly share the sink area,
+    // so a child is about half of its parent group side from the merge root. Each pseudo
+    // sink wastes one leaf wire, and each level costs one group side of the die as it adds
+    // a merge node (and possibly a buffer stage) on every source to sink path.
+    fn estimate_plan_cost(&self, branchs: &[u32]) -> f32 {
+        let width = (self.x_range.1 - self.x_range.0).max(1) as f32;
+        let height = (self.y_range.1 - self.y_range.0).max(1) as f32;
+        let area = width * height;
+        let mut wirelength = 0.0;
+        let mut groups = 1.0;
+        for b in branchs {
+            let side = (area / groups).sqrt();
+            groups *= *b as f32;
+            wirelength += groups * side / 2.0;
+        }
+        let leaf_length = (area / groups).sqrt();
+        let pseudo_sink = groups - self.sinks.len() as f32;
+        wirelength + pseudo_sink * leaf_length + branchs.len() as f32 * area.sqrt()
+    }
+
+    // Pseudo sink fills an empty leaf of grouping, it's placed next to the real sinks of
+    // its group so that it doesn't stretch the merge unit, on a location free of sinks.
+    fn add_dummy_sink(&mut self, location: Location) -> SinkIndex {
+        self.sinks.push(Sink {
+            name: format!("{}_dummy_{}", self.name, self.dummy_count),
+            location,
+            cap: self.dummy_cap,
+            latency: 0.0,
+            offset: 0.0,
+            dummy: true,
+        });
+        self.dummy_count += 1;
+        self.sinks.len() - 1
+    }
+}
+
+// sinks bucketed by DUMMY_PITCH, for finding where a pseudo sink is free of other sinks
+struct Occupancy(HashMap<Location, Vec<Location>>);
+
+impl Occupancy {
+    fn new(coords: &[Location]) -> Self {
+        let mut occupancy = Occupancy(HashMap::new());
+        for d in coords {
+            occupancy.insert(*d);
+        }
+        occupancy
+    }
+
+    fn bucket(d: Location) -> Location {
+        (d.0.div_euclid(DUMMY_PITCH), d.1.div_euclid(DUMMY_PITCH))
+    }
+
+    fn insert(&mut self, d: Location) {
+        self.0.entry(Self::bucket(d)).or_default().push(d);
+    }
+
+    fn is_free(&self, d: Location) -> bool {
+        let (bx, by) = Self::bucket(d);
+        let near =
+            |p: &Location| (p.0 - d.0).abs() < DUMMY_PITCH && (p.1 - d.1).abs() < DUMMY_PITCH;
+        (bx - 1..=bx + 1)
+            .flat_map(|x| (by - 1..=by + 1).map(move |y| (x, y)))
+            .filter_map(|b| self.0.get(&b))
+            .all(|sinks| !sinks.iter().any(near))
+    }
+
+    // free location on the pitch grid around `anchor` inside die area, from the nearest
+    // ring of the grid outwards. `anchor` is kept when the die is full.
+    fn find_free(&self, anchor: Location, die: Option<(Location, Location)>) -> Location {
+        let sinks: usize = self.0.values().map(|d| d.len()).sum();
+        for r in 0..=(sinks as i32 + 1) {
+            let free = (-r..=r)
+                .flat_map(|dx| (-r..=r).map(move |dy| (dx, dy)))
+                .filter(|(dx, dy)| dx.abs().max(dy.abs()) == r)
+                .map(|(dx, dy)| (anchor.0 + dx * DUMMY_PITCH, anchor.1 + dy * DUMMY_PITCH))
+                .filter(|d| is_inside(*d, die) && self.is_free(*d))
+                .min_by_key(|d| ((d.0 - anchor.0).abs() + (d.1 - anchor.1).abs(), *d));
+            if let Some(d) = free {
+                return d;
+            }
+        }
+        anchor
+    }
+}
+
+// weight of each sink and of a pseudo sink when balancing groups
+struct Weights {
+    sinks: Vec<f32>,
+    pseudo: f32,
+}
+
+// leaf of the tree in grouping order, pseudo leaf keeps where its sink should be placed
+enum Leaf {
+    Sink(SinkIndex),
+    Pseudo(Location),
 }
 
+// Sinks of one group and the number of tree leaves under it. There are more leaves than
+// sinks when pseudo sinks are needed, and they are placed at `anchor` of an empty group.
+struct SlotGroup {
+    sinks: Vec<SinkIndex>,
+    slots: usize,
+    anchor: Location,
+}
+
+impl SlotGroup {
+    // groups split from this group, in the order of `pieces` with `slots` leaves each
+    fn split(&self, coords: &[(i32, i32)], pieces: Vec<Vec<usize>>, slots: &[usize]) -> Vec<Self> {
+        pieces
+            .into_iter()
+            .zip(slots)
+            .map(|(sinks, slots)| SlotGroup {
+                anchor: sinks.first().map_or(self.anchor, |d| coords[*d]),
+                sinks,
+                slots: *slots,
+            })
+            .collect()
+    }
+}
+
+fn group(
+    coords: &[(i32, i32)],
+    classes: &[usize],
+    weights: &Weights,
+    branchs: &[u32],
+    target_num: usize,
+) -> Vec<Leaf> {
+    let center = get_center(coords);
+    let mut grps = vec![SlotGroup {
+        sinks: (0..coords.len()).collect(),
+        slots: target_num,
+        anchor: center,
+    }];
+    for b in branchs {
+        grps = grps
+            .iter()
+            .flat_map(|g| find_group(coords, classes, weights, center, g, *b as usize))
+            .collect();
+    }
+    get_leaves(grps)
+}
+
+// Cut the group into `grpn_next` sectors by polar angle, each sector has the same number of
+// leaves and its share of sink weight follows its leaves. Sinks of a lower offset class come
+// first, so a sector takes one class where it can.
 fn find_group(
     coords: &[(i32, i32)],
+    classes: &[usize],
+    weights: &Weights,
     center: (i32, i32),
-    grp2id: (u32, Vec<usize>),
-    grpn_next: u32,
-) -> Vec<(u32, Vec<usize>)> {
-    let mut grp_id_next = vec![];
-    let start_gid_next = (grpn_next - 1) * grp2id.0;
-    for i in 0..grpn_next {
-        grp_id_next.push(start_gid_next + i);
-    }
-    let locations: Vec<(i32, i32)> = grp2id.1.iter().map(|x| coords[*x]).collect();
-    let relative_location_phase: Vec<i32> = locations
-        .iter()
-        .map(|d| {
-            let x = (d.0 - center.0) as f32;
-            let y = (d.1 - center.1) as f32;
-            let phase: f32 = if y > 0.0 {
-                y.atan2(x)
-            } else {
-                y.atan2(x) + 2.0 * PI
-            };
-            (phase * 10000.0) as i32
-        })
-        .collect();
-    let mut pre_sort_data: Vec<(&usize, &i32)> = grp2id
-        .1
-        .iter()
-        .zip(&mut relative_location_phase.iter())
-        .collect();
-    pre_sort_data.sort_by(|a, b| a.1.cmp(&b.1));
-    let sorted_idx: Vec<usize> = pre_sort_data.iter().map(|x| x.0.clone()).collect();
-    let cut_step = sorted_idx.len() as u32 / grpn_next;
-    let mut result = Vec::new();
-    for (i, gid) in grp_id_next.iter().enumerate() {
-        let i = i as u32;
-        let start = (cut_step * i) as usize;
-        let end = (cut_step * (i + 1)) as usize;
-        let idxs: Vec<usize> = (&sorted_idx[start..end]).to_vec();
-
-        result.push((*gid, idxs))
+    grp: &SlotGroup,
+    grpn_next: usize,
+) -> Vec<SlotGroup> {
+    let mut sorted_idx = grp.sinks.clone();
+    sorted_idx.sort_by_key(|idx| (classes[*idx], get_phase(center, coords[*idx]), *idx));
+    let slots = vec![grp.slots / grpn_next; grpn_next];
+    let pieces = cut_by_weight(&sorted_idx, weights, &slots, &vec![1; grpn_next]);
+    grp.split(coords, pieces, &slots)
+}
+
+// Split every group of a level into `b` balanced groups by recursive median cuts, the
+// result keeps the same order as `group`: sinks of a lowest level group are consecutive.
+fn group_by_bipartition(
+    coords: &[(i32, i32)],
+    classes: &[usize],
+    weights: &Weights,
+    branchs: &[u32],
+    target_num: usize,
+) -> Vec<Leaf> {
+    let mut grps = vec![SlotGroup {
+        sinks: (0..coords.len()).collect(),
+        slots: target_num,
+        anchor: get_center(coords),
+    }];
+    for b in branchs {
+        grps = grps
+            .into_iter()
+            .flat_map(|g| {
+                let by_x = match get_extent(coords, &g.sinks) {
+                    (x_min, x_max, y_min, y_max) if !g.sinks.is_empty() => {
+                        x_max - x_min >= y_max - y_min
+                    }
+                    _ => true,
+                };
+                split_group(coords, classes, weights, g, *b as usize, by_x)
+            })
+            .collect();
+    }
+    get_leaves(grps)
+}
+
+// Cut `grp` into `k` groups of equal leaves. Each cut is at the weighted median along x or
+// y of its own sinks, and the axis alternates between nested cuts. Sinks of different offset
+// classes are cut apart first.
+fn split_group(
+    coords: &[(i32, i32)],
+    classes: &[usize],
+    weights: &Weights,
+    grp: SlotGroup,
+    k: usize,
+    by_x: bool,
+) -> Vec<SlotGroup> {
+    if k <= 1 {
+        return vec![grp];
+    }
+    let mut sorted = grp.sinks.clone();
+    sorted.sort_by_key(|idx| {
+        let d = coords[*idx];
+        if by_x {
+            (classes[*idx], d.0, d.1, *idx)
+        } else {
+            (classes[*idx], d.1, d.0, *idx)
+        }
+    });
+    let k_low = k / 2;
+    let slots = [grp.slots / k * k_low, grp.slots - grp.slots / k * k_low];
+    let pieces = cut_by_weight(&sorted, weights, &slots, &[k_low, k - k_low]);
+    let mut halves = grp.split(coords, pieces, &slots).into_iter();
+    let mut result = vec![];
+    for k in [k_low, k - k_low] {
+        if let Some(half) = halves.next() {
+            result.extend(split_group(coords, classes, weights, half, k, !by_x));
+        }
     }
     result
 }
 
+// Cut `sorted` sinks into consecutive pieces of `slots[i]` leaves, so that the weight of a
+// piece, counting its pseudo sinks, is in proportion to its leaves. A piece never takes
+// more sinks than its leaves, and when there are enough sinks it takes at least one for
+// each of the `parts[i]` groups it will be split into. Without spare leaves the pieces take
+// `slots[i]` sinks each and are balanced by exchanging sinks at the cuts.
+fn cut_by_weight(
+    sorted: &[usize],
+    weights: &Weights,
+    slots: &[usize],
+    parts: &[usize],
+) -> Vec<Vec<usize>> {
+    let total_slots: usize = slots.iter().sum();
+    let pseudo_sinks = total_slots.saturating_sub(sorted.len());
+    let total_weight: f32 = sorted.iter().map(|d| weights.sinks[*d]).sum::<f32>()
+        + pseudo_sinks as f32 * weights.pseudo;
+    let mut result = vec![];
+    let mut start = 0;
+    let mut acc_weight = 0.0;
+    let mut acc_slots = 0;
+    for i in 0..slots.len() {
+        let rest = sorted.len() - start;
+        let later_slots: usize = slots[i + 1..].iter().sum();
+        let later_parts: usize = parts[i + 1..].iter().sum();
+        let (mut min_take, mut max_take) = (rest.saturating_sub(later_slots), slots[i].min(rest));
+        if rest >= parts[i] + later_parts {
+            min_take = min_take.max(parts[i]);
+            max_take = max_take.min(rest - later_parts);
+        }
+        acc_slots += slots[i];
+        let target = total_weight * acc_slots as f32 / total_slots as f32;
+        // weight of the pieces so far when this piece ends at `end`, the leaves left are
+        // filled by pseudo sinks
+        let weight = |acc_weight: f32, end: usize| {
+            acc_weight + acc_slots.saturating_sub(end) as f32 * weights.pseudo
+        };
+        let mut end = start;
+        while end - start < max_take {
+            let next = acc_weight + weights.sinks[sorted[end]];
+            let closer =
+                (weight(next, end + 1) - target).abs() <= (weight(acc_weight, end) - target).abs();
+            if end - start >= min_take && !closer {
+                break;
+            }
+            acc_weight = next;
+            end += 1;
+        }
+        result.push(sorted[start..end].to_vec());
+        start = end;
+    }
+    exchange_at_cuts(&mut result, weights, slots);
+    result
+}
+
+// Exchange sinks between neighbouring pieces while it brings the weight of each piece
+// closer to its share of leaves. Only the quarter of each piece next to the cut takes part,
+// so that pieces stay compact in the sorted order.
+fn exchange_at_cuts(pieces: &mut [Vec<usize>], weights: &Weights, slots: &[usize]) {
+    let weight = |piece: &[usize], slots: usize| {
+        piece.iter().map(|d| weights.sinks[*d]).sum::<f32>()
+            + slots.saturating_sub(piece.len()) as f32 * weights.pseudo
+    };
+    for i in 1..pieces.len() {
+        let (low, high) = pieces.split_at_mut(i);
+        let (a, b) = (&mut low[i - 1], &mut high[0]);
+        let (window_a, window_b) = (a.len().div_ceil(4), b.len().div_ceil(4));
+        let share = slots[i - 1] as f32 / (slots[i - 1] + slots[i]).max(1) as f32;
+        for _ in 0..window_a.min(window_b) {
+            let (weight_a, weight_b) = (weight(a, slots[i - 1]), weight(b, slots[i]));
+            let excess = weight_a - (weight_a + weight_b) * share;
+            let best = (a.len() - window_a..a.len())
+                .flat_map(|x| (0..window_b).map(move |y| (x, y)))
+                .map(|(x, y)| {
+                    let moved = weights.sinks[a[x]] - weights.sinks[b[y]];
+                    (x, y, (excess - moved).abs())
+                })
+                .min_by(|p, q| p.2.partial_cmp(&q.2).unwrap_or(std::cmp::Ordering::Equal));
+            match best {
+                Some((x, y, error)) if error < excess.abs() => std::mem::swap(&mut a[x], &mut b[y]),
+                _ => break,
+            }
+        }
+    }
+}
+
+// groups of the lowest level have one leaf each
+fn get_leaves(grps: Vec<SlotGroup>) -> Vec<Leaf> {
+    grps.into_iter()
+        .map(|g| match g.sinks.first() {
+            Some(d) => Leaf::Sink(*d),
+            None => Leaf::Pseudo(g.anchor),
+        })
+        .collect()
+}
+
+// Offset class of each sink by its requested arrival, the offset minus the insertion delay
+// below the sink. Sorted requests stay in one class until a gap larger than OFFSET_GAP_RATIO
+// of their spread, so that grouping keeps sinks of similar offsets together and the wire
+// planned for skew stays short. All sinks are in class 0 for zero skew.
+fn get_offset_classes(sinks: &[Sink]) -> Vec<usize> {
+    let requests: Vec<f32> = sinks.iter().map(|s| s.offset - s.latency).collect();
+    let mut sorted: Vec<usize> = (0..sinks.len()).collect();
+    sorted.sort_by(|a, b| {
+        requests[*a]
+            .partial_cmp(&requests[*b])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut classes = vec![0; sinks.len()];
+    let (first, last) = match (sorted.first(), sorted.last()) {
+        (Some(first), Some(last)) => (requests[*first], requests[*last]),
+        _ => return classes,
+    };
+    let gap = (last - first) * OFFSET_GAP_RATIO;
+    let mut class = 0;
+    for pair in sorted.windows(2) {
+        if requests[pair[1]] - requests[pair[0]] > gap {
+            class += 1;
+        }
+        classes[pair[1]] = class;
+    }
+    classes
+}
+
+fn get_extent(coords: &[(i32, i32)], grp: &[usize]) -> (i32, i32, i32, i32) {
+    grp.iter().fold(
+        (i32::MAX, i32::MIN, i32::MAX, i32::MIN),
+        |(x_min, x_max, y_min, y_max), idx| {
+            let d = coords[*idx];
+            (
+                x_min.min(d.0),
+                x_max.max(d.0),
+                y_min.min(d.1),
+                y_max.max(d.1),
+            )
+        },
+    )
+}
+
+// angle of `d` around `center` in [0, 2PI), scaled to integer for sorting
+fn get_phase(center: (i32, i32), d: (i32, i32)) -> i32 {
+    let x = (d.0 - center.0) as f32;
+    let y = (d.1 - center.1) as f32;
+    let phase: f32 = if y > 0.0 {
+        y.atan2(x)
+    } else {
+        y.atan2(x) + 2.0 * PI
+    };
+    (phase * 10000.0) as i32
+}
+
 fn get_center(coords: &[(i32, i32)]) -> (i32, i32) {
     let mut n = 0;
     let mut x: i64 = 0;
@@ -232,3 +626,324 @@ fn get_center(coords: &[(i32, i32)]) -> (i32, i32) {
     }
     ((x / n) as i32, (y / n) as i32)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::collections::HashMap;
+
+    fn grid(width: i32, height: i32, pitch: i32) -> Vec<Location> {
+        (0..width * height)
+            .map(|i| ((i % width) * pitch, (i / width) * pitch))
+            .collect()
+    }
+
+    fn clusters(seed: u64, centers: &[Location], num: usize, radius: i32) -> Vec<Location> {
+        random_locations(seed, num, (-radius, radius))
+            .into_iter()
+            .enumerate()
+            .map(|(i, d)| {
+                let center = centers[i % centers.len()];
+                (center.0 + d.0, center.1 + d.1)
+            })
+            .collect()
+    }
+
+    fn new_cfg(max_branch: usize, grouping: GroupingStrategy) -> GenTopologyCfg {
+        GenTopologyCfg {
+            grouping: Some(grouping),
+            ..topology_cfg(max_branch)
+        }
+    }
+
+    // walk the tree from root, independent of check_consistency
+    fn check_structure(tree: &ClockTree, real_sinks: usize) {
+        assert_eq!(tree.fanout_map.len(), tree.tree_level);
+        assert_eq!(tree.length_map.len(), tree.tree_level);
+        let leaves: u32 = tree.fanout_map.values().product();
+        assert_eq!(tree.sinks.len(), leaves as usize);
+        assert_eq!(tree.sinks.iter().filter(|s| !s.dummy).count(), real_sinks);
+        assert_eq!(tree.dummy_count, tree.sinks.len() - real_sinks);
+
+        let mut seen: HashMap<SinkIndex, usize> = HashMap::new();
+        let mut stack = vec![(tree.root_node_index, 0)];
+        while let Some((nidx, level)) = stack.pop() {
+            match tree.nodes[nidx].node_owner {
+                NodeOwner::Sink(d) => {
+                    assert_eq!(level, tree.tree_level, "sink {} is at wrong level", d);
+                    *seen.entry(d).or_insert(0) += 1;
+                }
+                _ => {
+                    let midx = tree.get_node_merge_unit(nidx).unwrap();
+                    let childs: Vec<NodeIndex> = tree.get_merge_unit_load_nodes(midx).collect();
+                    assert_eq!(childs.len() as u32, tree.fanout_map[&level]);
+                    stack.extend(childs.into_iter().map(|c| (c, level + 1)));
+                }
+            }
+        }
+        assert_eq!(seen.len(), tree.sinks.len());
+        assert!(seen.values().all(|count| *count == 1));
+        tree.check_consistency().unwrap();
+    }
+
+    fn build(locations: &[Location], cfg: &GenTopologyCfg) -> ClockTree {
+        let mut tree = new_tree(locations, &[1.0]);
+        tree.gen_topology(cfg).unwrap();
+        check_structure(&tree, locations.len());
+        tree
+    }
+
+    // routed wirelength of the whole tree
+    fn wirelength(tree: &mut ClockTree) -> u32 {
+        tree.routing().unwrap().values().sum()
+    }
+
+    const STRATEGIES: [GroupingStrategy; 2] =
+        [GroupingStrategy::Angular, GroupingStrategy::Bipartition];
+
+    #[test]
+    fn grid_sinks() {
+        for grouping in STRATEGIES {
+            let tree = build(&grid(8, 8, 1000), &new_cfg(4, grouping));
+            assert!(tree.sinks.iter().all(|s| !s.dummy));
+        }
+    }
+
+    #[test]
+    fn single_sink() {
+        for grouping in STRATEGIES {
+            let tree = build(&[(500, 500)], &new_cfg(4, grouping));
+            assert_eq!(tree.tree_level, 1);
+            // pseudo sinks sit next to the real sink without overlapping it or each other
+            let near = |a: Location, b: Location| {
+                (a.0 - b.0).abs() < DUMMY_PITCH && (a.1 - b.1).abs() < DUMMY_PITCH
+            };
+            for (i, a) in tree.sinks.iter().enumerate() {
+                assert!(tree.sinks[i + 1..]
+                    .iter()
+                    .all(|b| !near(a.location, b.location)));
+                let distance = (a.location.0 - 500).abs() + (a.location.1 - 500).abs();
+                assert!(distance <= DUMMY_PITCH);
+            }
+        }
+    }
+
+    #[test]
+    fn prime_sink_number() {
+        for num in [2, 3, 7, 13, 97, 211] {
+            for grouping in STRATEGIES {
+                build(
+                    &random_locations(num as u64, num, (0, 10000)),
+                    &new_cfg(6, grouping),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn collinear_sinks() {
+        let horizontal: Vec<Location> = (0..37).map(|i| (i * 300, 2000)).collect();
+        let vertical: Vec<Location> = (0..37).map(|i| (2000, i * 300)).collect();
+        let stacked = vec![(1000, 1000); 10];
+        for locations in [horizontal, vertical, stacked] {
+            for grouping in STRATEGIES {
+                build(&locations, &new_cfg(4, grouping));
+            }
+        }
+    }
+
+    #[test]
+    fn clustered_sinks() {
+        let centers = [(1000, 1000), (9000, 1500), (5000, 8000)];
+        for grouping in STRATEGIES {
+            build(&clusters(7, &centers, 150, 400), &new_cfg(8, grouping));
+        }
+    }
+
+    #[test]
+    fn branch_bounds() {
+        let locations = random_locations(3, 50, (0, 5000));
+        let mut cfg = new_cfg(3, GroupingStrategy::Bipartition);
+        cfg.min_branch = Some(3);
+        let tree = build(&locations, &cfg);
+        assert!(tree.fanout_map.values().all(|b| *b == 3));
+
+        cfg.min_branch = Some(4);
+        assert!(new_tree(&locations, &[1.0]).gen_topology(&cfg).is_err());
+        assert!(matches!(
+            ClockTree::default().gen_topology(&cfg),
+            Err(CtsError::EmptySinks)
+        ));
+    }
+
+    #[test]
+    fn plan_many_sinks() {
+        let tree = new_tree(&random_locations(5, 100_000, (0, 2_000_000)), &[1.0]);
+        let start = std::time::Instant::now();
+        let branchs = tree.plan_branches(&topology_cfg(32)).unwrap();
+        assert!(
+            start.elapsed().as_secs() < 10,
+            "planning took {:?}",
+            start.elapsed()
+        );
+        let leaves: usize = branchs.iter().map(|b| *b as usize).product();
+        assert!(branchs.iter().all(|b| (2..=32).contains(b)));
+        assert!(leaves >= 100_000 && leaves as f32 <= 100_000.0 * MAX_LEAF_RATIO);
+        assert!(branchs.len() <= 4 + MAX_EXTRA_LEVELS);
+    }
+
+    #[test]
+    fn rebuild_topology() {
+        let cfg = new_cfg(4, GroupingStrategy::Angular);
+        let locations = random_locations(11, 30, (0, 5000));
+        let mut tree = build(&locations, &cfg);
+        let nodes = tree.nodes.len();
+        tree.gen_topology(&cfg).unwrap();
+        check_structure(&tree, locations.len());
+        assert_eq!(tree.nodes.len(), nodes);
+    }
+
+    #[test]
+    fn capacitance_balanced_groups() {
+        // a heavy macro and 6 light sinks on a line, padded to 8 leaves. The pseudo sink
+        // joins the macro instead of the far end of the line, as it balances the load.
+        let mut tree = new_tree(&(0..7).map(|i| (i * 100, 0)).collect::<Vec<_>>(), &[1.0]);
+        tree.sinks[0].cap = 10.0;
+        let mut cfg = new_cfg(2, GroupingStrategy::Bipartition);
+        cfg.min_branch = Some(2);
+        tree.gen_topology(&cfg).unwrap();
+        check_structure(&tree, 7);
+        let macro_node = tree
+            .nodes
+            .iter()
+            .position(|n| matches!(n.node_owner, NodeOwner::Sink(0)))
+            .unwrap();
+        let pair: Vec<NodeIndex> = (0..tree.merges.len())
+            .map(|midx| tree.get_merge_unit_load_nodes(midx).collect::<Vec<_>>())
+            .find(|childs| childs.contains(&macro_node))
+            .unwrap();
+        assert!(pair.iter().any(|n| match tree.nodes[*n].node_owner {
+            NodeOwner::Sink(d) => tree.sinks[d].dummy,
+            _ => false,
+        }));
+    }
+
+    // capacitance of the sinks below the node
+    fn subtree_cap(tree: &ClockTree, nidx: NodeIndex) -> f32 {
+        match tree.get_node_merge_unit(nidx) {
+            Some(midx) => tree
+                .get_merge_unit_load_nodes(midx)
+                .map(|child| subtree_cap(tree, child))
+                .sum(),
+            None => match tree.nodes[nidx].node_owner {
+                NodeOwner::Sink(d) => tree.sinks[d].cap,
+                _ => 0.0,
+            },
+        }
+    }
+
+    #[test]
+    fn capacitance_exchanges_sinks_at_cut() {
+        // 8 sinks on a line fill the 8 leaves, so the halves have 4 sinks each and only an
+        // exchange at the cut can balance the two heavy sinks next to it
+        let locations: Vec<Location> = (0..8).map(|i| (i * 100, 0)).collect();
+        let mut halves = vec![];
+        for balance in [GroupBalance::Count, GroupBalance::Capacitance] {
+            let mut tree = new_tree(&locations, &[1.0]);
+            tree.sinks[2].cap = 4.0;
+            tree.sinks[3].cap = 4.0;
+            let mut cfg = new_cfg(2, GroupingStrategy::Bipartition);
+            cfg.balance = Some(balance);
+            tree.gen_topology(&cfg).unwrap();
+            check_structure(&tree, 8);
+            let root = tree.get_node_merge_unit(tree.root_node_index).unwrap();
+            let mut caps: Vec<f32> = tree
+                .get_merge_unit_load_nodes(root)
+                .map(|child| subtree_cap(&tree, child))
+                .collect();
+            caps.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            halves.push(caps);
+        }
+        assert_eq!(halves[0], [4.0, 10.0]);
+        assert_eq!(halves[1], [7.0, 7.0]);
+    }
+
+    // sinks below the node
+    fn subtree_sinks(tree: &ClockTree, nidx: NodeIndex) -> Vec<SinkIndex> {
+        match tree.nodes[nidx].node_owner {
+            NodeOwner::Sink(d) => vec![d],
+            _ => {
+                let midx = tree.get_node_merge_unit(nidx).unwrap();
+                tree.get_merge_unit_load_nodes(midx)
+                    .flat_map(|child| subtree_sinks(tree, child))
+                    .collect()
+            }
+        }
+    }
+
+    #[test]
+    fn pseudo_sinks_weigh_dummy_load() {
+        // 6 sinks on a line padded to 8 leaves. Without dummy load the pseudo sinks join
+        // the heavy sink to balance it, a heavy dummy load sends them to the light end.
+        let locations: Vec<Location> = (0..6).map(|i| (i * 100, 0)).collect();
+        for (dummy_cap, pseudo_with_heavy) in [(0.0, 2), (3.0, 0)] {
+            let mut tree = new_tree(&locations, &[1.0]);
+            tree.sinks[0].cap = 3.0;
+            tree.dummy_cap = dummy_cap;
+            tree.gen_topology(&new_cfg(2, GroupingStrategy::Bipartition))
+                .unwrap();
+            check_structure(&tree, 6);
+            assert!(tree
+                .sinks
+                .iter()
+                .filter(|s| s.dummy)
+                .all(|s| s.cap == dummy_cap));
+            let root = tree.get_node_merge_unit(tree.root_node_index).unwrap();
+            let heavy_half = tree
+                .get_merge_unit_load_nodes(root)
+                .map(|child| subtree_sinks(&tree, child))
+                .find(|sinks| sinks.contains(&0))
+                .unwrap();
+            let pseudo = heavy_half.iter().filter(|d| tree.sinks[**d].dummy).count();
+            assert_eq!(pseudo, pseudo_with_heavy, "dummy cap {}", dummy_cap);
+        }
+    }
+
+    #[test]
+    fn offset_classes_stay_together() {
+        for grouping in STRATEGIES {
+            let mut tree = new_tree(&grid(8, 8, 1000), &[1.0]);
+            // checkerboard of early and late sinks, and a slightly later one
+            for (i, sink) in tree.sinks.iter_mut().enumerate() {
+                sink.offset = if (i % 8 + i / 8) % 2 == 0 { 0.0 } else { 0.1 };
+            }
+            tree.sinks[1].offset = 0.105;
+            tree.gen_topology(&new_cfg(4, grouping)).unwrap();
+            check_structure(&tree, 64);
+            let root = tree.get_node_merge_unit(tree.root_node_index).unwrap();
+            for child in tree.get_merge_unit_load_nodes(root) {
+                let sinks = subtree_sinks(&tree, child);
+                let late = sinks.iter().filter(|d| tree.sinks[**d].offset > 0.0);
+                assert!(late.count() % sinks.len() == 0, "{:?} grouping", grouping);
+            }
+        }
+    }
+
+    #[test]
+    fn golden_wirelength() {
+        let centers = [(2000, 2000), (18000, 3000), (10000, 16000), (3000, 15000)];
+        // (sinks, golden wirelength of Angular and Bipartition grouping)
+        let cases = [
+            (grid(16, 16, 500), [672464, 240000]),
+            (random_locations(2021, 500, (0, 20000)), [4700441, 1590545]),
+            (clusters(42, &centers, 400, 1500), [1961336, 4483961]),
+        ];
+        for (locations, golden) in cases.iter() {
+            for (grouping, expected) in STRATEGIES.iter().zip(golden) {
+                let mut tree = build(locations, &new_cfg(8, *grouping));
+                assert_eq!(wirelength(&mut tree), *expected, "{:?} grouping", grouping);
+            }
+        }
+    }
+}
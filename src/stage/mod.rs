@@ -2,4 +2,10 @@
 
 pub mod buffering;
 pub mod cfg;
+pub mod dme;
+pub mod export;
 pub mod gen_topology;
+pub mod obstacle;
+pub mod routing;
+pub mod skew;
+pub mod timing;
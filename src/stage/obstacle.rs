@@ -0,0 +1,461 @@
+// placement and routing blockages and fixed macros, as (lower left, upper right) rectangles
+use super::routing::{is_inside, manhattan_distance, simplify};
+use crate::model::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+type Rect = (Location, Location);
+
+impl ClockTree {
+    /// Move merge units and pseudo sinks out of obstacles
+    ///
+    /// A merge unit or pseudo sink strictly inside an obstacle is moved to the nearest
+    /// point outside every obstacle in the die area, so buffers inserted at merge units are
+    /// legal too. When a move lengthens a child beyond its planned wirelength, every child
+    /// of the level is lengthened by the same amount, which keeps the path length of every
+    /// sink the same. Return the number of moved merge units and pseudo sinks.
+    pub fn legalize(&mut self) -> usize {
+        if self.obstacles.is_empty() {
+            return 0;
+        }
+        let old_merges: Vec<Location> = self.merges.iter().map(|m| m.location).collect();
+        let old_sinks: Vec<Location> = self.sinks.iter().map(|s| s.location).collect();
+        let mut moved = 0;
+        let (obstacles, die) = (&self.obstacles, self.die_area);
+        let locations = self.merges.iter_mut().map(|m| &mut m.location).chain(
+            self.sinks
+                .iter_mut()
+                .filter(|s| s.dummy)
+                .map(|s| &mut s.location),
+        );
+        for location in locations {
+            if !is_blocked(*location, obstacles) {
+                continue;
+            }
+            match nearest_legal(*location, obstacles, die) {
+                Some(d) => {
+                    *location = d;
+                    moved += 1;
+                }
+                None => info!("no legal location around {:?}", location),
+            }
+        }
+        if moved == 0 {
+            return 0;
+        }
+
+        let old_location = |tree: &ClockTree, nidx: NodeIndex| match tree.nodes[nidx].node_owner {
+            NodeOwner::Sink(d) => old_sinks[d],
+            NodeOwner::Buffer(d) => old_merges[tree.buffers[d].load],
+            NodeOwner::MergeUnit(d) => old_merges[d],
+        };
+        let mut level_merges: Vec<MergeUnitIndex> = self
+            .get_node_merge_unit(self.root_node_index)
+            .into_iter()
+            .collect();
+        for level in 0..self.tree_level {
+            let mut increase = 0;
+            let mut next = vec![];
+            for midx in &level_merges {
+                let (old_root, root) = (old_merges[*midx], self.merges[*midx].location);
+                for (i, child) in self.get_merge_unit_load_nodes(*midx).enumerate() {
+                    let old_distance = manhattan_distance(old_location(self, child), old_root);
+                    let distance = manhattan_distance(self.get_node_location(child), root);
+                    let planned = self.merges[*midx].child_length(i).max(old_distance);
+                    increase = increase.max(distance.saturating_sub(planned));
+                    next.extend(self.get_node_merge_unit(child));
+                }
+            }
+            if increase > 0 {
+                detail!(
+                    "level {} is lengthened by {} for legalization",
+                    level,
+                    increase
+                );
+                for midx in &level_merges {
+                    self.merges[*midx].common_length += increase;
+                }
+                if let Some(length) = self.length_map.get_mut(&level) {
+                    *length += increase;
+                }
+            }
+            level_merges = next;
+        }
+        info!(
+            "{} merge units and pseudo sinks are moved out of obstacles",
+            moved
+        );
+        moved
+    }
+}
+
+// strictly inside an obstacle, the boundary is legal
+pub(crate) fn is_blocked(d: Location, obstacles: &[Rect]) -> bool {
+    obstacles
+        .iter()
+        .any(|(ll, ur)| ll.0 < d.0 && d.0 < ur.0 && ll.1 < d.1 && d.1 < ur.1)
+}
+
+// straight segment passing through the inside of an obstacle
+pub(crate) fn is_segment_blocked(a: Location, b: Location, obstacles: &[Rect]) -> bool {
+    let (x0, x1) = (a.0.min(b.0), a.0.max(b.0));
+    let (y0, y1) = (a.1.min(b.1), a.1.max(b.1));
+    obstacles
+        .iter()
+        .any(|(ll, ur)| x0 < ur.0 && ll.0 < x1 && y0 < ur.1 && ll.1 < y1)
+}
+
+// obstacles a route between `from` and `to` has to avoid, the ones holding an end are
+// passed through, such as a macro with its clock pin inside
+pub(crate) fn route_obstacles(from: Location, to: Location, obstacles: &[Rect]) -> Vec<Rect> {
+    obstacles
+        .iter()
+        .filter(|o| !is_blocked(from, &[**o]) && !is_blocked(to, &[**o]))
+        .cloned()
+        .collect()
+}
+
+// closed rectangles share a point
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.0 .0 <= b.1 .0 && b.0 .0 <= a.1 .0 && a.0 .1 <= b.1 .1 && b.0 .1 <= a.1 .1
+}
+
+fn contains(outer: &Rect, inner: &Rect) -> bool {
+    outer.0 .0 <= inner.0 .0
+        && outer.0 .1 <= inner.0 .1
+        && inner.1 .0 <= outer.1 .0
+        && inner.1 .1 <= outer.1 .1
+}
+
+fn near_obstacles(obstacles: &[Rect], window: &Rect) -> Vec<Rect> {
+    obstacles
+        .iter()
+        .filter(|o| overlaps(o, window))
+        .cloned()
+        .collect()
+}
+
+// Nearest location outside every obstacle in die area, it's on the grid of obstacle edges.
+// The obstacles are searched in a window around `d` doubled until a legal location is
+// within its radius, as a location there is blocked only by obstacles in the window.
+fn nearest_legal(d: Location, obstacles: &[Rect], die: Option<Rect>) -> Option<Location> {
+    // distance to the nearest edge of an obstacle holding `d`
+    let mut radius = obstacles
+        .iter()
+        .filter(|o| is_blocked(d, &[**o]))
+        .map(|(ll, ur)| (d.0 - ll.0).min(ur.0 - d.0).min(d.1 - ll.1).min(ur.1 - d.1))
+        .min()
+        .unwrap_or(0)
+        .max(1);
+    loop {
+        let window = ((d.0 - radius, d.1 - radius), (d.0 + radius, d.1 + radius));
+        let near = near_obstacles(obstacles, &window);
+        let bounded = near.len() < obstacles.len();
+        let xs = near.iter().flat_map(|(ll, ur)| vec![ll.0, ur.0]);
+        let ys: Vec<i32> = near
+            .iter()
+            .flat_map(|(ll, ur)| vec![ll.1, ur.1])
+            .chain(Some(d.1))
+            .collect();
+        let nearest = xs
+            .chain(Some(d.0))
+            .flat_map(|x| ys.iter().map(move |y| (x, *y)))
+            .filter(|p| !bounded || manhattan_distance(*p, d) <= radius as u32)
+            .filter(|p| is_inside(*p, die) && !is_blocked(*p, &near))
+            .min_by_key(|p| (manhattan_distance(*p, d), *p));
+        if nearest.is_some() || !bounded {
+            return nearest;
+        }
+        radius *= 2;
+    }
+}
+
+// Shortest rectilinear route from `from` to `to` around obstacles, as its corner points.
+// The route runs on the grid of obstacle edges inside die area, and takes the fewest
+// bends among the shortest ones. None when `to` can't be reached.
+//
+// Only the obstacles in a window around the ends are routed around. A route leaving the
+// window is longer than the distance plus twice the margin, so a route within that length
+// is the shortest, otherwise the margin is doubled until the window covers die area, or
+// every obstacle when there's no die area.
+pub(crate) fn route_around(
+    from: Location,
+    to: Location,
+    obstacles: &[Rect],
+    die: Option<Rect>,
+) -> Option<Vec<Location>> {
+    let ends = (
+        (from.0.min(to.0), from.1.min(to.1)),
+        (from.0.max(to.0), from.1.max(to.1)),
+    );
+    let outer = die.unwrap_or_else(|| {
+        let (ll, ur) = obstacles.iter().fold(ends, |(ll, ur), o| {
+            (
+                (ll.0.min(o.0 .0), ll.1.min(o.0 .1)),
+                (ur.0.max(o.1 .0), ur.1.max(o.1 .1)),
+            )
+        });
+        // a free line around every obstacle
+        ((ll.0 - 1, ll.1 - 1), (ur.0 + 1, ur.1 + 1))
+    });
+    let distance = manhattan_distance(from, to);
+    let mut margin = (distance / 2).max(1) as i32;
+    loop {
+        let window = (
+            (ends.0 .0 - margin, ends.0 .1 - margin),
+            (ends.1 .0 + margin, ends.1 .1 + margin),
+        );
+        let full = contains(&window, &outer);
+        let window = match die {
+            Some((ll, ur)) => (
+                (window.0 .0.max(ll.0), window.0 .1.max(ll.1)),
+                (window.1 .0.min(ur.0), window.1 .1.min(ur.1)),
+            ),
+            None => window,
+        };
+        let near = near_obstacles(obstacles, &window);
+        let route = route_in_window(from, to, &near, window);
+        let length = |points: &Vec<Location>| -> u32 {
+            points
+                .windows(2)
+                .map(|d| manhattan_distance(d[0], d[1]))
+                .sum()
+        };
+        match route {
+            Some(points) if length(&points) <= distance + 2 * margin as u32 => return Some(points),
+            route if full => return route,
+            _ => margin *= 2,
+        }
+    }
+}
+
+// route_around on the grid of obstacle edges and window edges inside the window
+fn route_in_window(
+    from: Location,
+    to: Location,
+    obstacles: &[Rect],
+    window: Rect,
+) -> Option<Vec<Location>> {
+    let grid = |ends: [i32; 2], edges: Vec<i32>, (low, high): (i32, i32)| {
+        let mut coords: Vec<i32> = edges
+            .into_iter()
+            .filter(|d| low <= *d && *d <= high)
+            .chain(ends.iter().cloned())
+            .chain(vec![low, high])
+            .collect();
+        coords.sort_unstable();
+        coords.dedup();
+        coords
+    };
+    let xs = grid(
+        [from.0, to.0],
+        obstacles
+            .iter()
+            .flat_map(|(ll, ur)| vec![ll.0, ur.0])
+            .collect(),
+        (window.0 .0, window.1 .0),
+    );
+    let ys = grid(
+        [from.1, to.1],
+        obstacles
+            .iter()
+            .flat_map(|(ll, ur)| vec![ll.1, ur.1])
+            .collect(),
+        (window.0 .1, window.1 .1),
+    );
+    let start = (
+        xs.binary_search(&from.0).ok()?,
+        ys.binary_search(&from.1).ok()?,
+    );
+    let end = (xs.binary_search(&to.0).ok()?, ys.binary_search(&to.1).ok()?);
+    let location = |(i, j): (usize, usize)| (xs[i], ys[j]);
+
+    // state is (grid point, direction entering it), direction 4 is the start
+    type State = ((usize, usize), usize);
+    let mut cost: HashMap<State, (u32, u32)> = HashMap::new();
+    let mut prev: HashMap<State, State> = HashMap::new();
+    let mut heap = BinaryHeap::new();
+    cost.insert((start, 4), (0, 0));
+    heap.push(Reverse(((0, 0), (start, 4))));
+    while let Some(Reverse((d, state))) = heap.pop() {
+        let (node, direction) = state;
+        if matches!(cost.get(&state), Some(c) if *c < d) {
+            continue;
+        }
+        if node == end {
+            let mut points = vec![location(node)];
+            let mut state = state;
+            while let Some(p) = prev.get(&state) {
+                points.push(location(p.0));
+                state = *p;
+            }
+            points.reverse();
+            return Some(simplify(&points));
+        }
+        let neighbors = [
+            (node.0.checked_sub(1).map(|i| (i, node.1)), 0),
+            (Some((node.0 + 1, node.1)).filter(|n| n.0 < xs.len()), 1),
+            (node.1.checked_sub(1).map(|j| (node.0, j)), 2),
+            (Some((node.0, node.1 + 1)).filter(|n| n.1 < ys.len()), 3),
+        ];
+        for (next, next_direction) in neighbors.iter() {
+            let next = match next {
+                Some(next) => *next,
+                None => continue,
+            };
+            let (a, b) = (location(node), location(next));
+            if is_segment_blocked(a, b, obstacles) {
+                continue;
+            }
+            let bend = if direction == 4 || direction == *next_direction {
+                0
+            } else {
+                1
+            };
+            let next_cost = (d.0 + manhattan_distance(a, b), d.1 + bend);
+            let next_state = (next, *next_direction);
+            if matches!(cost.get(&next_state), Some(c) if *c <= next_cost) {
+                continue;
+            }
+            cost.insert(next_state, next_cost);
+            prev.insert(next_state, state);
+            heap.push(Reverse((next_cost, next_state)));
+        }
+    }
+    None
+}
+
+// rectilinear route through the points doesn't touch itself, neighbor segments meet only
+// at their joint and the last one meets the first at the end of a ring
+pub(crate) fn is_simple(points: &[Location]) -> bool {
+    let segments: Vec<(Location, Location)> = points.windows(2).map(|d| (d[0], d[1])).collect();
+    let ring = points.len() > 2 && points.first() == points.last();
+    for i in 0..segments.len() {
+        for j in i + 1..segments.len() {
+            let (a, b) = (segments[i], segments[j]);
+            let low = (
+                a.0 .0.min(a.1 .0).max(b.0 .0.min(b.1 .0)),
+                a.0 .1.min(a.1 .1).max(b.0 .1.min(b.1 .1)),
+            );
+            let high = (
+                a.0 .0.max(a.1 .0).min(b.0 .0.max(b.1 .0)),
+                a.0 .1.max(a.1 .1).min(b.0 .1.max(b.1 .1)),
+            );
+            if low.0 > high.0 || low.1 > high.1 {
+                continue;
+            }
+            let joint = if j == i + 1 {
+                Some(a.1)
+            } else if ring && i == 0 && j == segments.len() - 1 {
+                Some(a.0)
+            } else {
+                None
+            };
+            if joint != Some(low) || low != high {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn corners_length(points: &[Location]) -> u32 {
+        points
+            .windows(2)
+            .map(|d| manhattan_distance(d[0], d[1]))
+            .sum()
+    }
+
+    #[test]
+    fn route_around_macro() {
+        let die = Some(((0, 0), (1000, 1000)));
+        let obstacles = [((400, 0), (600, 800))];
+        let (from, to) = ((0, 500), (1000, 500));
+        let corners = route_around(from, to, &obstacles, die).unwrap();
+        assert_eq!(corners[0], from);
+        assert_eq!(corners[corners.len() - 1], to);
+        assert_eq!(corners_length(&corners), 1000 + 2 * 300);
+        assert_eq!(corners, vec![from, (0, 800), (1000, 800), to]);
+        assert!(corners
+            .windows(2)
+            .all(|d| !is_segment_blocked(d[0], d[1], &obstacles)));
+        // walled off by the obstacle and die area
+        let wall = [((400, -10), (600, 1010))];
+        assert!(route_around(from, to, &wall, die).is_none());
+        assert!(is_simple(&corners));
+        assert!(!is_simple(&[(0, 0), (10, 0), (10, 10), (5, 10), (5, -5)]));
+    }
+
+    #[test]
+    fn search_window_grows() {
+        // a wall longer than the first window, and obstacles far from the route
+        let die = Some(((0, 0), (10000, 10000)));
+        let mut obstacles = vec![((40, -10), (60, 9000))];
+        obstacles.extend((0..50).map(|i| ((5000 + i * 80, 5000), (5040 + i * 80, 5040))));
+        let (from, to) = ((0, 500), (100, 500));
+        let corners = route_around(from, to, &obstacles, die).unwrap();
+        assert_eq!(corners, vec![from, (0, 9000), (100, 9000), to]);
+        assert_eq!(corners_length(&corners), 100 + 2 * 8500);
+
+        // the nearest edge of the macro holding the point is inside another one
+        let obstacles = [((0, 0), (1000, 1000)), ((900, 0), (1500, 1000))];
+        assert_eq!(nearest_legal((700, 500), &obstacles, die), Some((700, 0)));
+        assert_eq!(
+            nearest_legal((700, 500), &obstacles[..1], die),
+            Some((1000, 500))
+        );
+    }
+
+    #[test]
+    fn legalize_and_route_around_macro() {
+        let mut tree = ClockTree {
+            name: "clk".to_string(),
+            die_area: Some(((0, 0), (10000, 10000))),
+            obstacles: vec![((3000, 3000), (7000, 7000)), ((8000, 0), (8800, 2000))],
+            ..Default::default()
+        };
+        // the sink at (3500, 3500) is the clock pin of the macro
+        for i in 0..16 {
+            tree.sinks.push(Sink {
+                name: format!("ff_{}", i),
+                location: ((i % 4) * 3000 + 500, (i / 4) * 3000 + 500),
+                cap: 0.001,
+                ..Default::default()
+            });
+        }
+        tree.gen_topology(&topology_cfg(2)).unwrap();
+        tree.embed_dme(None).unwrap();
+        assert!(tree
+            .merges
+            .iter()
+            .any(|m| is_blocked(m.location, &tree.obstacles)));
+        assert!(tree.legalize() > 0);
+        assert!(tree
+            .merges
+            .iter()
+            .all(|m| !is_blocked(m.location, &tree.obstacles)));
+        tree.check_consistency().unwrap();
+
+        tree.routing().unwrap();
+        for midx in 0..tree.merges.len() {
+            let root = tree.merges[midx].location;
+            let routes = tree.get_merge_unit_routes(midx);
+            for (child, route) in tree.get_merge_unit_load_nodes(midx).zip(routes) {
+                let location = tree.get_node_location(child);
+                let obstacles = route_obstacles(location, root, &tree.obstacles);
+                assert!(route
+                    .iter()
+                    .all(|d| !is_segment_blocked(d.0, d.1, &obstacles)));
+            }
+        }
+        let lengths = sink_path_lengths(&tree);
+        // path length is balanced up to the parity of each level
+        let spread = lengths.iter().max().unwrap() - lengths.iter().min().unwrap();
+        assert!(spread <= tree.tree_level as u32, "spread {}", spread);
+    }
+}
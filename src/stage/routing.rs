@@ -0,0 +1,584 @@
+use super::obstacle::{is_segment_blocked, is_simple, route_around, route_obstacles};
+use crate::model::*;
+use cts_plugin::Path;
+use std::collections::HashMap;
+
+// child merge unit, planned wirelength and corners of the shortest route of a child
+type ChildRoute = (Option<MergeUnitIndex>, u32, Vec<Location>);
+
+impl ClockTree {
+    /// Route every merge unit in top-down order
+    ///
+    /// Each child is connected to the root of its merge unit with an L-shape path.
+    /// A child closer to the root than its planned wirelength is snaked with U-shape
+    /// lobes inside the die area, so that every child sees the common length of its merge
+    /// unit plus the extra length planned by DME or for useful skew. Common length is the
+    /// same in a level unless merge units are embedded by DME.
+    /// A child blocked by obstacles takes the shortest route around them. A detour longer
+    /// than the planned wirelength lengthens the path of the sinks below it, so the other
+    /// childs of its merge unit are lengthened by the excess, and so on up to the root, to
+    /// keep the path length of every sink the same.
+    /// The clock source, when it's set, is connected to the root node by the shortest
+    /// route as the trunk.
+    /// After routing, MergeUnit's path field and ClockTree's trunk field are updated and
+    /// the total routed wirelength of each level is returned.
+    pub fn routing(&mut self) -> Option<HashMap<ClockTreeLevel, u32>> {
+        let mut wirelength_map: HashMap<ClockTreeLevel, u32> = HashMap::new();
+        let mut snaking_length = 0;
+        let mut detour_length = 0;
+        // (childs, wirelength) short of their planned length
+        let mut shortfall = (0, 0);
+        // merge units of each level, and the childs of each merge unit
+        let mut levels = vec![vec![self.get_node_merge_unit(self.root_node_index)?]];
+        let mut childs: Vec<Vec<ChildRoute>> = vec![vec![]; self.merges.len()];
+        for level in 0..self.tree_level {
+            let mut next_merges = vec![];
+            for midx in &levels[level] {
+                let root = self.merges[*midx].location;
+                for (i, nidx) in self.get_merge_unit_load_nodes(*midx).enumerate() {
+                    let child = self.get_node_location(nidx);
+                    let distance = manhattan_distance(child, root);
+                    let planned = self.merges[*midx].child_length(i).max(distance);
+                    let corners = self.shortest_route(child, root);
+                    detour_length += route_length(&corners) - distance;
+                    let child_merge = self.get_node_merge_unit(nidx);
+                    childs[*midx].push((child_merge, planned, corners));
+                    next_merges.extend(child_merge);
+                }
+            }
+            levels.push(next_merges);
+        }
+        // bottom-up, the increase of path length below each merge unit by detours
+        let mut increase = vec![0; self.merges.len()];
+        for midx in levels.iter().rev().flatten() {
+            increase[*midx] = childs[*midx]
+                .iter()
+                .map(|(child, planned, corners)| {
+                    let below = child.map_or(0, |c| increase[c]);
+                    route_length(corners).saturating_sub(*planned) + below
+                })
+                .max()
+                .unwrap_or(0);
+        }
+        if increase[levels[0][0]] > 0 {
+            detail!(
+                "path length is lengthened by {} for detours around obstacles",
+                increase[levels[0][0]]
+            );
+        }
+
+        for (level, merges) in levels.iter().enumerate().take(self.tree_level) {
+            let mut level_length = 0;
+            for midx in merges {
+                self.merges[*midx].path.clear();
+                for (child, planned, corners) in std::mem::take(&mut childs[*midx]) {
+                    let target = planned + increase[*midx] - child.map_or(0, |c| increase[c]);
+                    let extra = target.saturating_sub(route_length(&corners));
+                    let (route, snaking) = self.route_with_obstacles(&corners, extra);
+                    level_length += route.iter().fold(0, |acc, x| acc + x.length()) as u32;
+                    self.merges[*midx].path.extend(route);
+                    snaking_length += snaking;
+                    // odd extra loses one unit by parity
+                    if extra - snaking > 1 {
+                        shortfall.0 += 1;
+                        shortfall.1 += extra - snaking;
+                    }
+                }
+            }
+            wirelength_map.insert(level, level_length);
+        }
+
+        self.trunk.clear();
+        if let Some(source) = self.source {
+            let root = self.get_node_location(self.root_node_index);
+            self.trunk = self
+                .route_with_obstacles(&self.shortest_route(source, root), 0)
+                .0;
+            let trunk_length: i32 = self.trunk.iter().map(|d| d.length()).sum();
+            detail!("trunk from source {:?} wirelength:{}", source, trunk_length);
+        }
+
+        let total_wire = wirelength_map.values().sum::<u32>();
+        info!(
+            "routing finished, routed wirelength:{}, snaking wirelength:{}",
+            total_wire, snaking_length
+        );
+        if detour_length > 0 {
+            info!("detour wirelength around obstacles:{}", detour_length);
+        }
+        if shortfall.0 > 0 {
+            info!(
+                "{} childs can't be snaked inside die area, short of wirelength:{}",
+                shortfall.0, shortfall.1
+            );
+        }
+        Some(wirelength_map)
+    }
+
+    // Corners of the shortest route from child to root. An L-shape is taken when either
+    // one is clear of obstacles, otherwise the route goes around them.
+    fn shortest_route(&self, from: Location, to: Location) -> Vec<Location> {
+        let vertical_first = simplify(&[from, (from.0, to.1), to]);
+        if self.obstacles.is_empty() {
+            return vertical_first;
+        }
+        let obstacles = route_obstacles(from, to, &self.obstacles);
+        let horizontal_first = simplify(&[from, (to.0, from.1), to]);
+        let is_clear = |corners: &[Location]| {
+            corners
+                .windows(2)
+                .all(|d| !is_segment_blocked(d[0], d[1], &obstacles))
+        };
+        if is_clear(&vertical_first) {
+            return vertical_first;
+        }
+        if is_clear(&horizontal_first) {
+            return horizontal_first;
+        }
+        route_around(from, to, &obstacles, self.die_area).unwrap_or_else(|| {
+            detail!("no route around obstacles from {:?} to {:?}", from, to);
+            vertical_first
+        })
+    }
+
+    // Route along the corners with `extra` snaking wire clear of obstacles, and return the
+    // paths and the added snaking wirelength. Snaking of the whole route is tried first,
+    // then snaking of each segment of it, and the one adding the most is taken.
+    fn route_with_obstacles(&self, corners: &[Location], extra: u32) -> (Vec<Path>, u32) {
+        let (from, to) = (corners[0], corners[corners.len() - 1]);
+        let obstacles = route_obstacles(from, to, &self.obstacles);
+        let is_clear = |points: &[Location]| {
+            points
+                .windows(2)
+                .all(|d| !is_segment_blocked(d[0], d[1], &obstacles))
+        };
+        if route_length(corners) == manhattan_distance(from, to) {
+            let (paths, snaking) = route_with_snaking(from, to, extra, self.die_area);
+            if obstacles.is_empty() || is_clear(&path_points(&paths)) {
+                return (paths, snaking);
+            }
+        }
+        let mut best = (corners.to_vec(), 0);
+        for i in 0..corners.len().saturating_sub(1) {
+            if best.1 + 1 >= extra {
+                break;
+            }
+            let (lobes, snaking) =
+                route_with_snaking(corners[i], corners[i + 1], extra, self.die_area);
+            let mut points = corners[..i].to_vec();
+            points.extend(path_points(&lobes));
+            points.extend(&corners[i + 2..]);
+            if snaking > best.1 && is_clear(&points) && is_simple(&simplify(&points)) {
+                best = (points, snaking);
+            }
+        }
+        (polyline_paths(&best.0), best.1)
+    }
+}
+
+impl ClockTree {
+    // routed wirelength over the manhattan distance of every child to its merge unit root
+    pub fn get_snaking_length(&self) -> u32 {
+        let mut snaking_length = 0;
+        for midx in 0..self.merges.len() {
+            let root = self.merges[midx].location;
+            let childs = self.get_merge_unit_load_nodes(midx);
+            for (child, route) in childs.zip(self.get_merge_unit_routes(midx)) {
+                let length: u32 = route.iter().map(|d| manhattan_distance(d.0, d.1)).sum();
+                let distance = manhattan_distance(self.get_node_location(child), root);
+                snaking_length += length.saturating_sub(distance);
+            }
+        }
+        snaking_length
+    }
+}
+
+pub(crate) fn manhattan_distance(a: Location, b: Location) -> u32 {
+    ((a.0 - b.0).abs() + (a.1 - b.1).abs()) as u32
+}
+
+fn route_length(corners: &[Location]) -> u32 {
+    corners
+        .windows(2)
+        .map(|d| manhattan_distance(d[0], d[1]))
+        .sum()
+}
+
+// points the paths run through
+fn path_points(paths: &[Path]) -> Vec<Location> {
+    let mut points: Vec<Location> = paths.first().map(|d| d.from).into_iter().collect();
+    for path in paths {
+        points.extend(path.turn);
+        points.push(path.to);
+    }
+    points
+}
+
+// vertical first, then horizontal
+fn l_shape(from: Location, to: Location) -> Path {
+    let turn = if from.0 == to.0 || from.1 == to.1 {
+        None
+    } else {
+        Some((from.0, to.1))
+    };
+    Path { from, turn, to }
+}
+
+// side of the bounding box of child and root where snaking lobes are placed
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Side {
+    Below,
+    Above,
+    Left,
+    Right,
+}
+
+impl Side {
+    // the lobes run along one axis and stick out along the other from the box side
+    fn is_horizontal(self) -> bool {
+        matches!(self, Side::Below | Side::Above)
+    }
+
+    fn outward(self) -> i64 {
+        match self {
+            Side::Below | Side::Left => -1,
+            Side::Above | Side::Right => 1,
+        }
+    }
+
+    // location of (coordinate along the run, coordinate along the lobes)
+    fn location(self, run: i64, out: i64) -> Location {
+        if self.is_horizontal() {
+            (run as i32, out as i32)
+        } else {
+            (out as i32, run as i32)
+        }
+    }
+
+    // (coordinate along the run, coordinate along the lobes) of a location
+    fn split(self, d: Location) -> (i64, i64) {
+        if self.is_horizontal() {
+            (d.0 as i64, d.1 as i64)
+        } else {
+            (d.1 as i64, d.0 as i64)
+        }
+    }
+}
+
+// how a side of the box of `from` and `to` can hold lobes: (side, room to the die edge,
+// span of the run). Room is unlimited without die area.
+fn side_capacity(
+    side: Side,
+    from: Location,
+    to: Location,
+    die: Option<(Location, Location)>,
+) -> (Side, i64, i64) {
+    let (from_run, from_out) = side.split(from);
+    let (to_run, to_out) = side.split(to);
+    let room = match die {
+        Some((lower_left, upper_right)) => {
+            if side.outward() > 0 {
+                side.split(upper_right).1 - from_out.max(to_out)
+            } else {
+                from_out.min(to_out) - side.split(lower_left).1
+            }
+        }
+        None => i64::MAX,
+    };
+    (side, room, (from_run - to_run).abs())
+}
+
+// Route from child to root with `extra` more wire than the manhattan distance, and return
+// the paths and the added snaking wirelength. The snaking wire is a row of `k` U-shape
+// lobes sticking out of one side of the box of child and root, each lobe adds twice its
+// height. The side away from the root is tried first, and the fewest lobes fitting in the
+// die area are used. When no side can hold the whole extra, the side holding the most is
+// filled. A rectilinear path keeps the parity of the manhattan distance, so odd extra
+// loses one unit. A child at the root is snaked through a ring one unit wide.
+fn route_with_snaking(
+    from: Location,
+    to: Location,
+    extra: u32,
+    die: Option<(Location, Location)>,
+) -> (Vec<Path>, u32) {
+    let half = (extra / 2) as i64;
+    if half == 0 {
+        return (vec![l_shape(from, to)], 0);
+    }
+    if from == to {
+        if half == 1 {
+            return (vec![l_shape(from, to)], 0);
+        }
+        let step = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .map(|d| (from.0 + d.0, from.1 + d.1))
+            .find(|d| is_inside(*d, die));
+        if let Some(step) = step {
+            let (mut paths, snaking) = route_with_snaking(from, step, extra - 2, die);
+            paths.push(l_shape(step, to));
+            return (paths, snaking + 2);
+        }
+        return (vec![l_shape(from, to)], 0);
+    }
+    let sides = if from.0 != to.0 {
+        if from.1 <= to.1 {
+            [Side::Below, Side::Above, Side::Left, Side::Right]
+        } else {
+            [Side::Above, Side::Below, Side::Left, Side::Right]
+        }
+    } else {
+        [Side::Left, Side::Right, Side::Below, Side::Above]
+    };
+    let usable: Vec<(Side, i64, i64)> = sides
+        .iter()
+        .map(|side| side_capacity(*side, from, to, die))
+        .filter(|(_, room, span)| *room >= 1 && *span >= 1)
+        .collect();
+    // k lobes and k - 1 gaps share the span, each at least one unit wide
+    let max_lobes = |span: i64| (span + 1) / 2;
+    let fit = usable
+        .iter()
+        .map(|(side, room, span)| (*side, (half - 1) / room + 1, *span))
+        .find(|(_, lobes, span)| *lobes <= max_lobes(*span));
+    let (side, lobes, span, height) = match fit {
+        Some((side, lobes, span)) => (side, lobes, span, half),
+        None => {
+            let capacity =
+                |(_, room, span): &&(Side, i64, i64)| room.saturating_mul(max_lobes(*span));
+            match usable.iter().max_by_key(capacity) {
+                Some((side, room, span)) => {
+                    let lobes = max_lobes(*span);
+                    (*side, lobes, *span, room * lobes)
+                }
+                None => return (vec![l_shape(from, to)], 0),
+            }
+        }
+    };
+
+    let (from_run, from_out) = side.split(from);
+    let (to_run, to_out) = side.split(to);
+    let base = if side.outward() > 0 {
+        from_out.max(to_out)
+    } else {
+        from_out.min(to_out)
+    };
+    let direction = (to_run - from_run).signum();
+    let segments = 2 * lobes - 1;
+    let bound = |i: i64| from_run + direction * (span * i / segments);
+    let mut points = vec![from];
+    for j in 0..lobes {
+        // lobe heights differ by at most one unit
+        let lobe = height / lobes + if j < height % lobes { 1 } else { 0 };
+        let top = base + side.outward() * lobe;
+        points.push(side.location(bound(2 * j), base));
+        points.push(side.location(bound(2 * j), top));
+        points.push(side.location(bound(2 * j + 1), top));
+        points.push(side.location(bound(2 * j + 1), base));
+    }
+    points.push(to);
+    (polyline_paths(&points), 2 * height as u32)
+}
+
+pub(crate) fn is_inside(d: Location, die: Option<(Location, Location)>) -> bool {
+    match die {
+        Some((lower_left, upper_right)) => {
+            (lower_left.0..=upper_right.0).contains(&d.0)
+                && (lower_left.1..=upper_right.1).contains(&d.1)
+        }
+        None => true,
+    }
+}
+
+// Paths along rectilinear points, each path takes two segments with the turn between them.
+fn polyline_paths(points: &[Location]) -> Vec<Path> {
+    let corners = simplify(points);
+    if corners.len() < 2 {
+        return vec![l_shape(points[0], points[points.len() - 1])];
+    }
+    let mut paths = vec![];
+    let mut i = 0;
+    while i + 1 < corners.len() {
+        if i + 2 < corners.len() {
+            paths.push(Path {
+                from: corners[i],
+                turn: Some(corners[i + 1]),
+                to: corners[i + 2],
+            });
+            i += 2;
+        } else {
+            paths.push(Path {
+                from: corners[i],
+                turn: None,
+                to: corners[i + 1],
+            });
+            i += 1;
+        }
+    }
+    paths
+}
+
+// drop repeated points and points in the middle of a straight line
+pub(crate) fn simplify(points: &[Location]) -> Vec<Location> {
+    let mut corners: Vec<Location> = vec![];
+    for d in points {
+        if corners.last() == Some(d) {
+            continue;
+        }
+        if corners.len() >= 2 {
+            let (a, b) = (corners[corners.len() - 2], corners[corners.len() - 1]);
+            let forward = |a: i32, b: i32, c: i32| (b - a).signum() == (c - b).signum();
+            if (a.0 == b.0 && b.0 == d.0 && forward(a.1, b.1, d.1))
+                || (a.1 == b.1 && b.1 == d.1 && forward(a.0, b.0, d.0))
+            {
+                corners.pop();
+            }
+        }
+        corners.push(*d);
+    }
+    corners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{new_tree, route_length, sink_path_lengths, topology_cfg};
+    use std::collections::HashSet;
+
+    // Check the paths run from `from` to `to` inside the die without crossing themselves,
+    // and return the routed wirelength
+    fn check_route(
+        from: Location,
+        to: Location,
+        paths: &[Path],
+        die: Option<(Location, Location)>,
+    ) -> u32 {
+        let mut points = vec![from];
+        for path in paths {
+            assert_eq!(*points.last().unwrap(), path.from);
+            points.extend(path.turn);
+            points.push(path.to);
+        }
+        assert_eq!(*points.last().unwrap(), to);
+        let mut visited = HashSet::new();
+        let mut length = 0;
+        for d in points.windows(2) {
+            assert!(
+                d[0].0 == d[1].0 || d[0].1 == d[1].1,
+                "{:?} isn't straight",
+                d
+            );
+            let step = ((d[1].0 - d[0].0).signum(), (d[1].1 - d[0].1).signum());
+            let mut p = d[0];
+            while p != d[1] {
+                p = (p.0 + step.0, p.1 + step.1);
+                assert!(is_inside(p, die), "{:?} is outside die", p);
+                assert!(
+                    visited.insert(p) || p == to,
+                    "route crosses itself at {:?}",
+                    p
+                );
+                length += 1;
+            }
+        }
+        length
+    }
+
+    #[test]
+    fn snaking_without_die() {
+        let (from, to) = ((0, 0), (300, 200));
+        let (paths, snaking) = route_with_snaking(from, to, 400, None);
+        assert_eq!(snaking, 400);
+        assert_eq!(check_route(from, to, &paths, None), 900);
+        // odd extra loses one unit by parity
+        let (paths, snaking) = route_with_snaking(from, to, 41, None);
+        assert_eq!(snaking, 40);
+        assert_eq!(check_route(from, to, &paths, None), 540);
+    }
+
+    #[test]
+    fn snaking_inside_die() {
+        let die = Some(((0, 0), (1000, 1000)));
+        // 30 units of room below and above, the lobes are folded along the run
+        let (from, to) = ((100, 970), (900, 30));
+        let (paths, snaking) = route_with_snaking(from, to, 1000, die);
+        assert_eq!(snaking, 1000);
+        assert_eq!(check_route(from, to, &paths, die), 1740 + 1000);
+        assert!(paths.len() > 3);
+
+        // a child at the root is snaked through a ring
+        let (paths, snaking) = route_with_snaking((1000, 1000), (1000, 1000), 60, die);
+        assert_eq!(snaking, 60);
+        assert_eq!(check_route((1000, 1000), (1000, 1000), &paths, die), 60);
+
+        // only 10 units of room above, the most is snaked and the route stays inside die
+        let (from, to) = ((0, 0), (1000, 990));
+        let (paths, snaking) = route_with_snaking(from, to, 100000, die);
+        assert_eq!(snaking, 10 * 500 * 2);
+        assert_eq!(check_route(from, to, &paths, die), 1990 + snaking);
+    }
+
+    #[test]
+    fn routed_length_matches_plan() {
+        let mut tree = ClockTree {
+            name: "clk".to_string(),
+            die_area: Some(((0, 0), (3000, 3000))),
+            ..Default::default()
+        };
+        let locations = [(0, 0), (2000, 0), (10, 1990), (1000, 1000)];
+        for (i, location) in locations.iter().enumerate() {
+            tree.sinks.push(Sink {
+                name: format!("ff_{}", i),
+                location: *location,
+                ..Default::default()
+            });
+        }
+        tree.nodes = (0..4)
+            .map(|i| Node {
+                node_owner: NodeOwner::Sink(i),
+                next_node: if i < 3 { Some(i + 1) } else { None },
+            })
+            .collect();
+        tree.nodes.push(Node {
+            node_owner: NodeOwner::MergeUnit(0),
+            next_node: None,
+        });
+        tree.merges.push(MergeUnit {
+            first_node: 0,
+            location: (1000, 1000),
+            common_length: 2400,
+            extra_length: vec![0, 0, 0, 600],
+            ..Default::default()
+        });
+        tree.root_node_index = 4;
+        tree.tree_level = 1;
+        tree.fanout_map.insert(0, 4);
+        tree.length_map.insert(0, 2400);
+        tree.check_consistency().unwrap();
+
+        let wirelength = tree.routing().unwrap();
+        assert_eq!(wirelength[&0], 2400 * 4 + 600);
+        let distances: u32 = locations
+            .iter()
+            .map(|d| manhattan_distance(*d, (1000, 1000)))
+            .sum();
+        assert_eq!(tree.get_snaking_length(), 2400 * 4 + 600 - distances);
+        let routes = tree.get_merge_unit_routes(0);
+        for (i, route) in routes.iter().enumerate() {
+            assert_eq!(route_length(route), tree.merges[0].child_length(i));
+        }
+    }
+
+    #[test]
+    fn detour_lengthens_its_subtree() {
+        let locations = [(0, 0), (2000, 0), (0, 10000), (2000, 10000)];
+        let mut tree = new_tree(&locations, &[0.001]);
+        tree.gen_topology(&topology_cfg(2)).unwrap();
+        // ff_0 detours by 200 to its merge unit at (1000, 0)
+        tree.obstacles = vec![((400, -100), (600, 100))];
+        tree.routing().unwrap();
+        let lengths = sink_path_lengths(&tree);
+        assert!(lengths.iter().all(|d| *d == lengths[0]), "{:?}", lengths);
+        // the detour, ff_1 and the other merge unit are lengthened, not ff_2 and ff_3
+        assert_eq!(tree.get_snaking_length(), 3 * 200);
+    }
+}
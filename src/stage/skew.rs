@@ -0,0 +1,226 @@
+use super::routing::manhattan_distance;
+use crate::model::*;
+
+// passes of planning wire and propagating slew, buffer delay depends on the slew given by
+// the wire planned in the previous pass
+const PLAN_PASSES: usize = 3;
+
+// electrical state of the tree under planning
+struct SkewPlan {
+    node_cap: Vec<f32>,   // capacitance seen at node input
+    node_slew: Vec<f32>,  // transition at node input
+    merge_load: Vec<f32>, // load seen at merge unit root
+}
+
+impl ClockTree {
+    /// Plan the extra wirelength of each child for the requested arrival offsets
+    ///
+    /// In bottom-up order, every merge unit lengthens the wire to its early childs, so that
+    /// arrival time minus requested offset of every real sink below it is the same. Pseudo
+    /// sinks request nothing. Wire delay is Elmore delay of the unrouted wire, and buffer
+    /// delay takes the slew propagated through the wire of the previous pass. The result is
+    /// stored in MergeUnit's extra_length field and routed by the routing stage.
+    ///
+    /// Grouping keeps sinks of similar offsets together and buffering weighs the offset error,
+    /// so the wire planned here mostly balances offsets within a class. Sinks of far apart
+    /// offsets still under one merge unit are met by a long extra wire.
+    pub fn plan_skew(
+        &mut self,
+        buffer_lib: &BufferLib,
+        wire: &WireModel,
+        input_slew: f32,
+    ) -> Option<()> {
+        let mut plan = SkewPlan {
+            node_cap: vec![0.0; self.nodes.len()],
+            node_slew: vec![input_slew; self.nodes.len()],
+            merge_load: vec![0.0; self.merges.len()],
+        };
+        for _ in 0..PLAN_PASSES {
+            for merge in self.merges.iter_mut() {
+                merge.extra_length.clear();
+            }
+            self.plan_node(self.root_node_index, buffer_lib, wire, &mut plan)?;
+            self.propagate_slew(buffer_lib, wire, input_slew, &mut plan)?;
+        }
+        Some(())
+    }
+
+    // Fill capacitance at the node input and return the delay from the node input to its
+    // sinks minus their requested offset. The delay is None when only pseudo sinks are below.
+    fn plan_node(
+        &mut self,
+        nidx: NodeIndex,
+        buffer_lib: &BufferLib,
+        wire: &WireModel,
+        plan: &mut SkewPlan,
+    ) -> Option<Option<f32>> {
+        let midx = match self.nodes[nidx].node_owner {
+            NodeOwner::Sink(d) => {
+                let sink = &self.sinks[d];
+                plan.node_cap[nidx] = sink.cap;
+                if sink.dummy {
+                    return Some(None);
+                }
+                return Some(Some(sink.latency - sink.offset));
+            }
+            _ => self.get_node_merge_unit(nidx)?,
+        };
+        let childs: Vec<NodeIndex> = self.get_merge_unit_load_nodes(midx).collect();
+        let mut child_delays = vec![];
+        for child in &childs {
+            child_delays.push(self.plan_node(*child, buffer_lib, wire, plan)?);
+        }
+
+        let (r, c) = wire.get_length_rc(1.0);
+        let common_length = self.merges[midx].common_length as f32;
+        // a child farther than the common length is routed with its distance
+        let root = self.merges[midx].location;
+        let base_length: Vec<f32> = childs
+            .iter()
+            .map(|child| manhattan_distance(self.get_node_location(*child), root) as f32)
+            .map(|length| length.max(common_length))
+            .collect();
+        let target = childs
+            .iter()
+            .zip(&base_length)
+            .zip(&child_delays)
+            .filter_map(|((child, length), delay)| {
+                delay.map(|d| unrouted_delay(wire, *length, plan.node_cap[*child]) + d)
+            })
+            .fold(None, |acc: Option<f32>, d| {
+                Some(acc.map_or(d, |a| a.max(d)))
+            });
+        let mut extra_length = vec![];
+        let mut load = 0.0;
+        for ((child, base), delay) in childs.iter().zip(&base_length).zip(&child_delays) {
+            let cap = plan.node_cap[*child];
+            let length = match (target, delay) {
+                (Some(target), Some(delay)) => {
+                    let length = solve_elmore_length(
+                        r as f64,
+                        c as f64,
+                        cap as f64,
+                        (target - delay) as f64,
+                    );
+                    (length as f32).max(*base)
+                }
+                _ => *base,
+            };
+            extra_length.push((length - common_length).round() as u32);
+            load += c * length + cap;
+        }
+        self.merges[midx].extra_length = extra_length;
+        plan.merge_load[midx] = load;
+
+        match self.nodes[nidx].node_owner {
+            NodeOwner::Buffer(d) => {
+                let model = buffer_lib.get_model(&self.buffers[d].model_name)?;
+                plan.node_cap[nidx] = model.input_cap;
+                let delay = model.get_delay(plan.node_slew[nidx], load);
+                Some(target.map(|t| t + delay))
+            }
+            _ => {
+                plan.node_cap[nidx] = load;
+                Some(target)
+            }
+        }
+    }
+
+    // transition at every node input through the planned wire, from the source to sinks
+    fn propagate_slew(
+        &self,
+        buffer_lib: &BufferLib,
+        wire: &WireModel,
+        input_slew: f32,
+        plan: &mut SkewPlan,
+    ) -> Option<()> {
+        let root_cap = plan.node_cap[self.root_node_index];
+        let trunk_delay = match self.source {
+            Some(source) => {
+                let root = self.get_node_location(self.root_node_index);
+                unrouted_delay(wire, manhattan_distance(source, root) as f32, root_cap)
+            }
+            None => 0.0,
+        };
+        // (node, slew at driver output, wire delay from driver)
+        let mut stack = vec![(self.root_node_index, input_slew, trunk_delay)];
+        while let Some((nidx, mut driver_slew, mut wire_delay)) = stack.pop() {
+            let slew = degrade_slew(driver_slew, wire_delay);
+            plan.node_slew[nidx] = slew;
+            let midx = match self.nodes[nidx].node_owner {
+                NodeOwner::Sink(_) => continue,
+                NodeOwner::Buffer(d) => {
+                    let model = buffer_lib.get_model(&self.buffers[d].model_name)?;
+                    let midx = self.buffers[d].load;
+                    driver_slew = model.get_transition(slew, plan.merge_load[midx]);
+                    wire_delay = 0.0;
+                    midx
+                }
+                NodeOwner::MergeUnit(d) => d,
+            };
+            let root = self.merges[midx].location;
+            for (i, child) in self.get_merge_unit_load_nodes(midx).enumerate() {
+                let length = manhattan_distance(self.get_node_location(child), root);
+                let length = length.max(self.merges[midx].child_length(i)) as f32;
+                let delay = unrouted_delay(wire, length, plan.node_cap[child]);
+                stack.push((child, driver_slew, wire_delay + delay));
+            }
+        }
+        Some(())
+    }
+}
+
+// Elmore delay of unrouted wire with `length` and `load` at its end
+fn unrouted_delay(wire: &WireModel, length: f32, load: f32) -> f32 {
+    chain_delay(&[wire.get_length_rc(length)], load, DelayMetric::Elmore)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn buffer_lib() -> BufferLib {
+        let lut =
+            LutModel::new(vec![0.01, 0.5], vec![0.001, 0.1], vec![vec![0.02, 0.1]; 2]).unwrap();
+        let model = BufferModel {
+            area: 1.0,
+            input_cap: 0.002,
+            max_cap: None,
+            cell_rise: lut.clone(),
+            cell_fall: lut.clone(),
+            rise_transition: lut.clone(),
+            fall_transition: lut,
+        };
+        BufferLib::new(vec![("BUF".to_string(), model)]).unwrap()
+    }
+
+    #[test]
+    fn plan_skew_converges() {
+        let wire = new_wire();
+        let buffer_lib = buffer_lib();
+        let mut tree = new_tree(&random_locations(13, 40, (0, 20000)), &[0.002]);
+        tree.gen_topology(&topology_cfg(4)).unwrap();
+        // buffers at level 1, so that buffer delay follows the planned wire
+        let root = tree.get_node_merge_unit(tree.root_node_index).unwrap();
+        let childs: Vec<NodeIndex> = tree.get_merge_unit_load_nodes(root).collect();
+        for nidx in childs {
+            tree.insert_buffer("BUF", tree.buffers.len(), nidx).unwrap();
+        }
+        // offsets far beyond the wire delay of the lowest level, on sinks of every group
+        let leaf_length = tree.length_map[&(tree.tree_level - 1)] as f32;
+        let level_delay = unrouted_delay(&wire, leaf_length, 0.002);
+        for (i, sink) in tree.sinks.iter_mut().filter(|s| !s.dummy).enumerate() {
+            sink.offset = [0.0, 3.0, 5.0][i % 3] * level_delay;
+        }
+        tree.plan_skew(&buffer_lib, &wire, 0.05).unwrap();
+        tree.routing().unwrap();
+        let report = tree
+            .timing_analysis(&buffer_lib, &wire, 0.05, 10.0, DelayMetric::Elmore)
+            .unwrap();
+        assert!(report.max_offset_error < 0.05 * level_delay);
+        for s in report.sinks.iter().filter(|s| !s.dummy) {
+            assert!((s.offset - s.requested).abs() < 0.05 * level_delay);
+        }
+    }
+}
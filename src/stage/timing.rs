@@ -0,0 +1,205 @@
+use crate::model::*;
+
+use std::fmt;
+
+pub struct SinkTiming {
+    pub sink: SinkIndex,
+    pub name: String,
+    pub dummy: bool,  // pseudo sink, left out of latency and skew
+    pub latency: f32, // including insertion delay of generated clock tree driven by the sink
+    pub transition: f32,
+    pub offset: f32,    // achieved arrival offset, relative to the earliest real sink
+    pub requested: f32, // requested arrival offset, relative to the least requested one
+}
+
+// result of static timing analysis on clock tree, latency and skew are among real sinks
+pub struct TimingReport {
+    pub sinks: Vec<SinkTiming>,
+    pub max_latency: f32,
+    pub min_latency: f32,
+    pub skew: f32,
+    pub max_offset_error: f32, // largest spread of achieved minus requested offset
+    pub max_transition: f32,
+    pub transition_violations: Vec<(NodeIndex, f32)>, // node and its input transition
+    pub cap_violations: Vec<(BufferIndex, f32)>,      // buffer and its load capacitance
+    pub seed: Option<u64>,                            // seed of the synthesis run, to reproduce it
+}
+
+impl fmt::Display for TimingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed:{}", seed)?;
+        }
+        let real = self.sinks.iter().filter(|s| !s.dummy);
+        writeln!(f, "sinks:{}", real.clone().count())?;
+        writeln!(f, "max latency:{}", self.max_latency)?;
+        writeln!(f, "min latency:{}", self.min_latency)?;
+        writeln!(f, "skew:{}", self.skew)?;
+        let offsets = self.sinks.iter().any(|s| s.requested != 0.0);
+        if offsets {
+            writeln!(f, "max offset error:{}", self.max_offset_error)?;
+        }
+        writeln!(f, "max transition:{}", self.max_transition)?;
+        writeln!(
+            f,
+            "transition violations:{}",
+            self.transition_violations.len()
+        )?;
+        write!(f, "capacitance violations:{}", self.cap_violations.len())?;
+        for s in real {
+            write!(
+                f,
+                "\n  {} latency:{} transition:{}",
+                s.name, s.latency, s.transition
+            )?;
+            if offsets {
+                write!(f, " offset:{} requested:{}", s.offset, s.requested)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ClockTree {
+    // Propagate arrival time and transition from the source with `input_slew` to every sink.
+    // Buffer delay and transition are from its LutModel at its load, wire delay is computed
+    // on merge unit path by `metric` and transition degrades along wire from the last driver.
+    pub fn timing_analysis(
+        &self,
+        buffer_lib: &BufferLib,
+        wire: &WireModel,
+        input_slew: f32,
+        max_slew: f32,
+        metric: DelayMetric,
+    ) -> Option<TimingReport> {
+        // capacitance seen at each node input, and load of each merge unit
+        let mut node_cap = vec![0.0; self.nodes.len()];
+        let mut merge_load = vec![0.0; self.merges.len()];
+        self.fill_node_cap(
+            self.root_node_index,
+            buffer_lib,
+            wire,
+            &mut node_cap,
+            &mut merge_load,
+        )?;
+
+        let mut report = TimingReport {
+            sinks: vec![],
+            max_latency: f32::MIN,
+            min_latency: f32::MAX,
+            skew: 0.0,
+            max_offset_error: 0.0,
+            max_transition: 0.0,
+            transition_violations: vec![],
+            cap_violations: vec![],
+            seed: None,
+        };
+        // wire from clock source to root node when the source is set
+        let rc: Vec<(f32, f32)> = self
+            .get_trunk_route()
+            .iter()
+            .map(|(from, to)| wire.get_segment_rc(*from, *to))
+            .collect();
+        let trunk_delay = chain_delay(&rc, node_cap[self.root_node_index], metric);
+        // (node, arrival time, transition at driver output, wire delay from driver)
+        let mut stack = vec![(self.root_node_index, trunk_delay, input_slew, trunk_delay)];
+        while let Some((nidx, mut arrival, mut driver_slew, mut wire_delay)) = stack.pop() {
+            let slew = degrade_slew(driver_slew, wire_delay);
+            report.max_transition = report.max_transition.max(slew);
+            if slew > max_slew {
+                report.transition_violations.push((nidx, slew));
+            }
+            let midx = match self.nodes[nidx].node_owner {
+                NodeOwner::Sink(d) => {
+                    report.sinks.push(SinkTiming {
+                        sink: d,
+                        name: self.sinks[d].name.clone(),
+                        dummy: self.sinks[d].dummy,
+                        latency: arrival + self.sinks[d].latency,
+                        transition: slew,
+                        offset: 0.0,
+                        requested: 0.0,
+                    });
+                    continue;
+                }
+                NodeOwner::Buffer(d) => {
+                    let model = buffer_lib.get_model(&self.buffers[d].model_name)?;
+                    let load = merge_load[self.buffers[d].load];
+                    if model.max_cap.is_some_and(|max_cap| load > max_cap) {
+                        report.cap_violations.push((d, load));
+                    }
+                    arrival += model.get_delay(slew, load);
+                    driver_slew = model.get_transition(slew, load);
+                    wire_delay = 0.0;
+                    self.buffers[d].load
+                }
+                NodeOwner::MergeUnit(d) => d,
+            };
+            let childs: Vec<NodeIndex> = self.get_merge_unit_load_nodes(midx).collect();
+            let child_caps: Vec<f32> = childs.iter().map(|c| node_cap[*c]).collect();
+            let delays = self.get_merge_unit_wire_delay(midx, wire, &child_caps, metric);
+            for (child, delay) in childs.into_iter().zip(delays) {
+                stack.push((child, arrival + delay, driver_slew, wire_delay + delay));
+            }
+        }
+        if report.sinks.iter().all(|s| s.dummy) {
+            return None;
+        }
+        report.sinks.sort_by_key(|s| s.sink);
+        // latency, skew and offsets are among real sinks, pseudo sinks request nothing
+        let real = |s: &&SinkTiming| !s.dummy;
+        for s in report.sinks.iter().filter(real) {
+            report.max_latency = report.max_latency.max(s.latency);
+            report.min_latency = report.min_latency.min(s.latency);
+        }
+        report.skew = report.max_latency - report.min_latency;
+        let earliest = report.sinks.iter().filter(real).map(|s| s.latency);
+        let earliest = earliest.fold(f32::MAX, f32::min);
+        let least = report.sinks.iter().filter(real);
+        let least = least
+            .map(|s| self.sinks[s.sink].offset)
+            .fold(f32::MAX, f32::min);
+        for s in report.sinks.iter_mut().filter(|s| !s.dummy) {
+            s.offset = s.latency - earliest;
+            s.requested = self.sinks[s.sink].offset - least;
+        }
+        let errors = report
+            .sinks
+            .iter()
+            .filter(real)
+            .map(|s| s.offset - s.requested);
+        let (low, high) = errors.fold((f32::MAX, f32::MIN), |(l, h), e| (l.min(e), h.max(e)));
+        if low <= high {
+            report.max_offset_error = high - low;
+        }
+        Some(report)
+    }
+
+    fn fill_node_cap(
+        &self,
+        nidx: NodeIndex,
+        buffer_lib: &BufferLib,
+        wire: &WireModel,
+        node_cap: &mut Vec<f32>,
+        merge_load: &mut Vec<f32>,
+    ) -> Option<f32> {
+        let midx = match self.nodes[nidx].node_owner {
+            NodeOwner::Sink(d) => {
+                node_cap[nidx] = self.sinks[d].cap;
+                return Some(node_cap[nidx]);
+            }
+            _ => self.get_node_merge_unit(nidx)?,
+        };
+        let childs: Vec<NodeIndex> = self.get_merge_unit_load_nodes(midx).collect();
+        let mut child_caps = vec![];
+        for child in childs {
+            child_caps.push(self.fill_node_cap(child, buffer_lib, wire, node_cap, merge_load)?);
+        }
+        merge_load[midx] = self.get_merge_unit_load(midx, wire, &child_caps);
+        node_cap[nidx] = match self.nodes[nidx].node_owner {
+            NodeOwner::Buffer(d) => buffer_lib.get_model(&self.buffers[d].model_name)?.input_cap,
+            _ => merge_load[midx],
+        };
+        Some(node_cap[nidx])
+    }
+}
@@ -0,0 +1,91 @@
+// fixtures shared by the unit tests of the stages
+use crate::model::*;
+use crate::stage::cfg::GenTopologyCfg;
+
+// sink locations from a fixed seed, by a linear congruential generator so that golden
+// numbers don't depend on the rand crate
+pub fn random_locations(seed: u64, num: usize, range: (i32, i32)) -> Vec<Location> {
+    let mut state = seed;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        range.0 + ((state >> 33) % (range.1 - range.0) as u64) as i32
+    };
+    (0..num).map(|_| (next(), next())).collect()
+}
+
+// unsynthesized tree with sinks ff_0, ff_1, ... at `locations`, capacitance cycles `caps`
+pub fn new_tree(locations: &[Location], caps: &[f32]) -> ClockTree {
+    let mut tree = ClockTree {
+        name: "clk".to_string(),
+        x_range: (
+            locations.iter().map(|d| d.0).min().unwrap(),
+            locations.iter().map(|d| d.0).max().unwrap(),
+        ),
+        y_range: (
+            locations.iter().map(|d| d.1).min().unwrap(),
+            locations.iter().map(|d| d.1).max().unwrap(),
+        ),
+        ..Default::default()
+    };
+    for (i, (location, cap)) in locations.iter().zip(caps.iter().cycle()).enumerate() {
+        tree.sinks.push(Sink {
+            name: format!("ff_{}", i),
+            location: *location,
+            cap: *cap,
+            ..Default::default()
+        });
+    }
+    tree
+}
+
+pub fn topology_cfg(max_branch: usize) -> GenTopologyCfg {
+    GenTopologyCfg {
+        max_branch,
+        min_branch: None,
+        grouping: None,
+        balance: None,
+        merging: None,
+    }
+}
+
+pub fn new_wire() -> WireModel {
+    let rc = LayerRc {
+        unit_res: 0.0001,
+        unit_cap: 0.00002,
+    };
+    WireModel {
+        horizontal: rc,
+        vertical: rc,
+    }
+}
+
+pub fn route_length(route: &[(Location, Location)]) -> u32 {
+    route
+        .iter()
+        .map(|(a, b)| ((a.0 - b.0).abs() + (a.1 - b.1).abs()) as u32)
+        .sum()
+}
+
+// routed wirelength from the root to each real sink
+pub fn sink_path_lengths(tree: &ClockTree) -> Vec<u32> {
+    let mut lengths = vec![];
+    let mut stack = vec![(tree.root_node_index, 0)];
+    while let Some((nidx, length)) = stack.pop() {
+        let midx = match tree.nodes[nidx].node_owner {
+            NodeOwner::Sink(d) => {
+                if !tree.sinks[d].dummy {
+                    lengths.push(length);
+                }
+                continue;
+            }
+            _ => tree.get_node_merge_unit(nidx).unwrap(),
+        };
+        let routes = tree.get_merge_unit_routes(midx);
+        for (child, route) in tree.get_merge_unit_load_nodes(midx).zip(routes) {
+            stack.push((child, length + route_length(&route)));
+        }
+    }
+    lengths
+}